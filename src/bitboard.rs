@@ -42,6 +42,43 @@ pub fn generate_magic_tables() -> MagicTables {
     }
 }
 
+// Squares attacked by a rook on the given square, up to and including the first piece in each
+// direction
+pub fn rook_attacks(position: u8, occupied: u64) -> u64 {
+    let position = position as usize;
+    let blockers = without_edges(ROOK_MOVE_MASKS[position], position) & occupied;
+    let index = blockers.wrapping_mul(MAGIC_TABLES.rook_magics[position]) >> (64 - RBITS[position]);
+    MAGIC_TABLES.rook_attacks[position][index as usize]
+}
+
+// Squares attacked by a bishop on the given square, up to and including the first piece in each
+// direction
+pub fn bishop_attacks(position: u8, occupied: u64) -> u64 {
+    let position = position as usize;
+    let blockers = without_edges(BISHOP_MOVE_MASKS[position], position) & occupied;
+    let index =
+        blockers.wrapping_mul(MAGIC_TABLES.bishop_magics[position]) >> (64 - BBITS[position]);
+    MAGIC_TABLES.bishop_attacks[position][index as usize]
+}
+
+// Removes the edges of the board a piece on the given square can't see past, leaving the squares
+// whose occupancy changes its attacks
+fn without_edges(mut mask: u64, position: usize) -> u64 {
+    if position < 56 {
+        mask &= !EIGHTH_RANK;
+    }
+    if position > 7 {
+        mask &= !FIRST_RANK;
+    }
+    if position % 8 < 7 {
+        mask &= !H_FILE;
+    }
+    if !position.is_multiple_of(8) {
+        mask &= !A_FILE;
+    }
+    mask
+}
+
 // Returns an attack table and associated magic number for one piece and position
 fn find_magics(piece: u8, position: u8) -> (Vec<u64>, u64) {
     // Gets list of blockers and attack maps. blockers[i] maps to attacks[i]
@@ -374,6 +411,18 @@ mod tests {
         print_binary_board(table[index as usize]);
         println!("");
     }
+    #[test]
+    fn slider_attacks() {
+        // rook on d4 with pieces on d6 and b4
+        let occupied = 1 << 43 | 1 << 25;
+        let expected = (D_FILE & !(1 << 27) & !(1 << 51) & !(1 << 59))
+            | (FOURTH_RANK & !(1 << 24) & !(1 << 27));
+        assert_eq!(rook_attacks(27, occupied), expected);
+
+        // bishop on a1 blocked on c3
+        assert_eq!(bishop_attacks(0, 1 << 18), 1 << 9 | 1 << 18);
+    }
+
     #[test]
     fn rook_legal_bitboards() {
         // For position 0
@@ -0,0 +1,293 @@
+use crate::eval::piece_boards;
+use crate::kpk::probe_kpk;
+use crate::params::*;
+use crate::utils::*;
+
+// Squares like b1 and a2. a1 is dark
+pub const LIGHT_SQUARES: u64 = 0x55AA55AA55AA55AA;
+
+// Scale factors are out of this. The normal score is multiplied by scale / NORMAL_SCALE
+pub const NORMAL_SCALE: i32 = 64;
+const OPPOSITE_BISHOPS_SCALE: i32 = 32;
+
+// Base score for a won endgame the specialised evaluators know how to play, on top of the
+// winning side's material, so the search heads for them
+const KNOWN_WIN: i32 = 1000;
+// Per step of the losing king from the centre, and per step the kings are closer together
+const PUSH_TO_EDGE: i32 = 20;
+const PUSH_CLOSE: i32 = 10;
+const PUSH_TO_CORNER: i32 = 20;
+// Per rank the pawn has advanced in a won KPK ending
+const PUSH_PAWN: i32 = 20;
+
+// What endgame knowledge has to say about a position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    // The score in centipawns from White's point of view, replacing the normal evaluation
+    Exact(&'static str, i32),
+    // The normal score scaled by this over NORMAL_SCALE
+    Scale(&'static str, i32),
+}
+
+// Non-king pieces of each side, indexed like piece_boards: pawns, knights, bishops, rooks, queens
+struct Material {
+    counts: [[u8; 5]; 2],
+    pieces: [[u64; 6]; 2],
+}
+
+impl Material {
+    fn new(board: &Board) -> Material {
+        let (white, black) = piece_boards(board);
+        let mut counts = [[0; 5]; 2];
+        for piece in 0..5 {
+            counts[0][piece] = count_ones(white[piece]);
+            counts[1][piece] = count_ones(black[piece]);
+        }
+        Material {
+            counts,
+            pieces: [white, black],
+        }
+    }
+
+    fn is_bare(&self, side: usize) -> bool {
+        self.counts[side].iter().all(|&count| count == 0)
+    }
+
+    // Whether a side has exactly the given pieces besides its king
+    fn has(&self, side: usize, pieces: [u8; 5]) -> bool {
+        self.counts[side] == pieces
+    }
+}
+
+// Neither side can ever mate: bare kings, a lone minor piece, two knights against a bare king, or
+// nothing but bishops that all stand on the same colour
+pub fn is_dead_draw(board: &Board) -> bool {
+    dead_draw(&Material::new(board))
+}
+
+fn dead_draw(material: &Material) -> bool {
+    let [white, black] = material.counts;
+    let pawns_or_majors = white[0] + black[0] + white[3] + black[3] + white[4] + black[4];
+    if pawns_or_majors > 0 {
+        return false;
+    }
+
+    let minors = white[1] + white[2] + black[1] + black[2];
+    if minors <= 1 {
+        return true;
+    }
+    for side in 0..2 {
+        if material.has(side, [0, 2, 0, 0, 0]) && material.is_bare(1 - side) {
+            return true;
+        }
+    }
+
+    let knights = white[1] + black[1];
+    let bishops = material.pieces[0][2] | material.pieces[1][2];
+    knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0)
+}
+
+pub fn endgame_verdict(board: &Board, params: &EvalParams) -> Option<Verdict> {
+    let material = Material::new(board);
+    // most positions have too much on the board for any of this
+    let pieces: u8 = material.counts.iter().flat_map(|c| c[1..].iter()).sum();
+    if pieces > 3 {
+        return None;
+    }
+
+    if dead_draw(&material) {
+        return Some(Verdict::Exact("insufficient material", 0));
+    }
+
+    for (strong, white) in [(0, true), (1, false)] {
+        let weak = 1 - strong;
+        if !material.is_bare(weak) {
+            continue;
+        }
+        let sign = if white { 1 } else { -1 };
+        let (strong_king, weak_king) = if white {
+            (board.white_king, board.black_king)
+        } else {
+            (board.black_king, board.white_king)
+        };
+        let strong_king = strong_king.trailing_zeros() as u8;
+        let weak_king = weak_king.trailing_zeros() as u8;
+        // the winning side's endgame material plus progress towards mate
+        let mating = |pieces: &[usize], edge: i32| {
+            let value: i32 = pieces
+                .iter()
+                .map(|&piece| params.weights[PIECE_VALUE + piece].1)
+                .sum();
+            KNOWN_WIN + value + edge + PUSH_CLOSE * (7 - distance(strong_king, weak_king))
+        };
+
+        if material.has(strong, [0, 0, 0, 0, 1]) {
+            let score = mating(&[4], PUSH_TO_EDGE * centre_distance(weak_king));
+            return Some(Verdict::Exact("KQK", sign * score));
+        }
+        if material.has(strong, [0, 0, 0, 1, 0]) {
+            let score = mating(&[3], PUSH_TO_EDGE * centre_distance(weak_king));
+            return Some(Verdict::Exact("KRK", sign * score));
+        }
+        if material.has(strong, [1, 0, 0, 0, 0]) {
+            let pawn = material.pieces[strong][0].trailing_zeros() as u8;
+            if !probe_kpk(strong_king, weak_king, pawn, white, board.turn) {
+                return Some(Verdict::Exact("KPK", 0));
+            }
+            let rank = if white { pawn / 8 } else { 7 - pawn / 8 } as i32;
+            let score = mating(&[0], PUSH_PAWN * rank);
+            return Some(Verdict::Exact("KPK", sign * score));
+        }
+        if material.has(strong, [0, 1, 1, 0, 0]) {
+            // only the corners the bishop can cover can be mated in
+            let light = material.pieces[strong][2] & LIGHT_SQUARES != 0;
+            let corners: [u8; 2] = if light { [7, 56] } else { [0, 63] };
+            let corner = corners
+                .iter()
+                .map(|&corner| manhattan(weak_king, corner))
+                .min()
+                .unwrap();
+            let score = mating(&[1, 2], PUSH_TO_CORNER * (14 - corner));
+            return Some(Verdict::Exact("KBNK", sign * score));
+        }
+
+        if wrong_bishop(&material, strong, white, weak_king) {
+            return Some(Verdict::Scale("rook pawn with the wrong bishop", 0));
+        }
+    }
+
+    // each side has one bishop, on different colours, and nothing else but pawns
+    let bishops_only = (0..2).all(|side| {
+        let [_, knights, bishops, rooks, queens] = material.counts[side];
+        knights == 0 && bishops == 1 && rooks == 0 && queens == 0
+    });
+    if bishops_only {
+        let white_light = material.pieces[0][2] & LIGHT_SQUARES != 0;
+        let black_light = material.pieces[1][2] & LIGHT_SQUARES != 0;
+        if white_light != black_light {
+            return Some(Verdict::Scale(
+                "opposite coloured bishops",
+                OPPOSITE_BISHOPS_SCALE,
+            ));
+        }
+    }
+
+    None
+}
+
+// Bishop and pawns that all stand on the same rook file, when the bishop can't cover the
+// promotion square and the defending king has reached it
+fn wrong_bishop(material: &Material, strong: usize, white: bool, weak_king: u8) -> bool {
+    let [pawns, knights, bishops, rooks, queens] = material.counts[strong];
+    if pawns == 0 || knights + rooks + queens > 0 || bishops != 1 {
+        return false;
+    }
+
+    let pawn_board = material.pieces[strong][0];
+    let file = if pawn_board & !A_FILE == 0 {
+        0
+    } else if pawn_board & !H_FILE == 0 {
+        7
+    } else {
+        return false;
+    };
+    let promotion = if white { 56 + file } else { file };
+    let bishop_light = material.pieces[strong][2] & LIGHT_SQUARES != 0;
+    let promotion_light = LIGHT_SQUARES & (1 << promotion) != 0;
+    bishop_light != promotion_light && distance(weak_king, promotion) <= 1
+}
+
+// King moves between two squares
+fn distance(a: u8, b: u8) -> i32 {
+    let files = (a % 8) as i32 - (b % 8) as i32;
+    let ranks = (a / 8) as i32 - (b / 8) as i32;
+    files.abs().max(ranks.abs())
+}
+
+fn manhattan(a: u8, b: u8) -> i32 {
+    let files = (a % 8) as i32 - (b % 8) as i32;
+    let ranks = (a / 8) as i32 - (b / 8) as i32;
+    files.abs() + ranks.abs()
+}
+
+// 0 on the four centre squares up to 6 in the corners
+fn centre_distance(square: u8) -> i32 {
+    let file = (square % 8) as i32;
+    let rank = (square / 8) as i32;
+    (3 - file).max(file - 4) + (3 - rank).max(rank - 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(fen: &str) -> Option<Verdict> {
+        endgame_verdict(&fen_to_board(fen), &params())
+    }
+
+    fn exact(fen: &str) -> i32 {
+        match verdict(fen) {
+            Some(Verdict::Exact(_, score)) => score,
+            other => panic!("{fen} gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn dead_draws() {
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2K3 b - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+            "4k3/8/8/2b5/8/8/8/2B1K3 w - - 0 1",
+        ] {
+            assert!(is_dead_draw(&fen_to_board(fen)), "{fen}");
+        }
+        for fen in [
+            "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/3n4/8/8/8/8/1N2KN2 w - - 0 1",
+        ] {
+            assert!(!is_dead_draw(&fen_to_board(fen)), "{fen}");
+        }
+    }
+
+    #[test]
+    fn drawish_scaling() {
+        assert_eq!(
+            verdict("4k3/5p2/3b4/8/8/8/4PP2/3BK3 w - - 0 1"),
+            Some(Verdict::Scale("opposite coloured bishops", 32))
+        );
+        // h8 is dark, the bishop is light
+        assert!(matches!(
+            verdict("7k/8/8/7P/8/8/8/3BK3 w - - 0 1"),
+            Some(Verdict::Scale(_, 0))
+        ));
+        // the right bishop wins
+        assert_eq!(verdict("7k/8/8/7P/8/8/8/2B1K3 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn mating_drives_to_corner() {
+        assert!(exact("8/8/8/3k4/8/8/8/R3K3 w - - 0 1") > 1000);
+        assert!(exact("k7/8/8/8/8/8/8/R3K3 w - - 0 1") > exact("8/8/8/3k4/8/8/8/R3K3 w - - 0 1"));
+        assert!(exact("k7/8/1K6/8/8/8/8/7q b - - 0 1") < exact("k7/8/8/8/8/8/8/4K2q b - - 0 1"));
+
+        // a dark squared bishop mates on a1 or h8, not a8
+        let right = exact("8/8/8/8/8/8/8/k1K1BN2 w - - 0 1");
+        let wrong = exact("k7/8/2K5/8/8/8/8/4BN2 w - - 0 1");
+        assert!(right > wrong);
+    }
+
+    #[test]
+    fn kpk() {
+        assert_eq!(
+            verdict("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"),
+            Some(Verdict::Exact("KPK", 0))
+        );
+        assert!(exact("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1") > 1000);
+        assert!(exact("8/8/8/3p4/3k4/8/3K4/8 w - - 0 1") < -1000);
+        // further up the board is better
+        assert!(exact("k7/8/4P3/8/8/8/8/K7 w - - 0 1") > exact("k7/8/8/4P3/8/8/8/K7 w - - 0 1"));
+    }
+}
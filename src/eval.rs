@@ -1,44 +1,902 @@
+use crate::bitboard::{bishop_attacks, rook_attacks};
+use crate::endgame::*;
+use crate::material::*;
 use crate::movegen::*;
+use crate::params::*;
+use crate::pawns::*;
 use crate::utils::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-// Bad evaluation function
-pub fn eval(board: &Board) -> f32 {
-    if legal_moves(board).len() == 0 {
-        if in_check(board, board.turn) {
-            if board.turn {
-                return f32::NEG_INFINITY;
+// How much each piece counts towards the game phase
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+// The phase with all pieces on the board. 0 means only kings and pawns are left
+pub const MAX_PHASE: i32 = 24;
+
+// Attack units per square of the king zone hit, for knights, bishops, rooks and queens
+const KING_ATTACK_WEIGHTS: [i32; 4] = [2, 2, 3, 5];
+
+// Typical number of safe squares for knights, bishops, rooks and queens. Mobility is scored per
+// square above or below these
+const MOBILITY_BASELINE: [i32; 4] = [4, 6, 7, 13];
+
+// d4, e4, d5 and e5
+const CENTRE: u64 = (D_FILE | E_FILE) & (FOURTH_RANK | FIFTH_RANK);
+// Where each side's space is counted: the c to f files on its own second to fourth ranks
+const SPACE_ZONES: [u64; 2] = [
+    (C_FILE | D_FILE | E_FILE | F_FILE) & (SECOND_RANK | THIRD_RANK | FOURTH_RANK),
+    (C_FILE | D_FILE | E_FILE | F_FILE) & (FIFTH_RANK | SIXTH_RANK | SEVENTH_RANK),
+];
+
+// Size of the evaluation cache in megabytes unless the EvalCache option says otherwise
+pub const DEFAULT_EVAL_CACHE_MB: usize = 1;
+
+// Static evaluations already worked out, keyed by Zobrist hash. Each thread keeps its own cache
+// with this many entries, a power of two, or none at all when it is 0.
+static EVAL_CACHE_ENTRIES: AtomicUsize =
+    AtomicUsize::new((DEFAULT_EVAL_CACHE_MB << 20) / size_of::<EvalEntry>());
+
+#[derive(Clone, Copy, Default)]
+struct EvalEntry {
+    key: u64,
+    score: f32,
+}
+
+thread_local! {
+    static EVAL_CACHE: RefCell<Vec<EvalEntry>> = const { RefCell::new(Vec::new()) };
+}
+
+// Sizes the cache to the most entries that fit in the given megabytes. Threads pick the new size
+// up the next time they probe.
+pub fn set_eval_cache_size(megabytes: usize) {
+    let entries = (megabytes << 20) / size_of::<EvalEntry>();
+    let entries = if entries == 0 {
+        0
+    } else {
+        1 << entries.ilog2()
+    };
+    EVAL_CACHE_ENTRIES.store(entries, Ordering::Relaxed);
+    clear_eval_cache();
+}
+
+// Forgets every cached evaluation on this thread, for a new game or when the evaluation changes
+pub fn clear_eval_cache() {
+    EVAL_CACHE.with(|cache| cache.borrow_mut().clear());
+}
+
+// Runs f on this thread's cache, resized first if the size has changed
+fn with_eval_cache<T>(f: impl FnOnce(&mut [EvalEntry]) -> T) -> T {
+    EVAL_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let entries = EVAL_CACHE_ENTRIES.load(Ordering::Relaxed);
+        if cache.len() != entries {
+            *cache = vec![EvalEntry::default(); entries];
+        }
+        f(&mut cache)
+    })
+}
+
+// The cached evaluation of the position with this hash, if there is one
+pub fn probe_eval_cache(key: u64) -> Option<f32> {
+    with_eval_cache(|cache| {
+        let entry = cache.get(key as usize & cache.len().wrapping_sub(1))?;
+        (entry.key == key).then_some(entry.score)
+    })
+}
+
+pub fn store_eval_cache(key: u64, score: f32) {
+    with_eval_cache(|cache| {
+        let index = key as usize & cache.len().wrapping_sub(1);
+        if let Some(entry) = cache.get_mut(index) {
+            *entry = EvalEntry { key, score };
+        }
+    })
+}
+
+// Receives the features of a position one at a time: count times the weight at index, for
+// White or Black. The same feature code either adds up a score or records the counts for the
+// tuner, so the two can't drift apart.
+pub trait EvalSink {
+    fn add(&mut self, index: usize, white: bool, count: i32);
+}
+
+// Adds the weights up for each side from its own point of view, White's first
+pub struct Score<'a> {
+    params: &'a EvalParams,
+    pub sides: [(i32, i32); 2],
+}
+
+impl<'a> Score<'a> {
+    pub fn new(params: &'a EvalParams) -> Score<'a> {
+        Score {
+            params,
+            sides: [(0, 0); 2],
+        }
+    }
+
+    // White minus Black, as (middlegame, endgame)
+    pub fn total(&self) -> (i32, i32) {
+        let [white, black] = self.sides;
+        (white.0 - black.0, white.1 - black.1)
+    }
+}
+
+impl EvalSink for Score<'_> {
+    fn add(&mut self, index: usize, white: bool, count: i32) {
+        let (mg, eg) = self.params.weights[index];
+        let side = &mut self.sides[if white { 0 } else { 1 }];
+        side.0 += count * mg;
+        side.1 += count * eg;
+    }
+}
+
+// How many times each weight counts, White minus Black. What the tuner fits against.
+impl EvalSink for Vec<(usize, i32)> {
+    fn add(&mut self, index: usize, white: bool, count: i32) {
+        self.push((index, if white { count } else { -count }));
+    }
+}
+
+// Static evaluation in pawns from White's point of view. Checkmate and stalemate are left to the
+// search, which knows when there are no legal moves.
+pub fn eval(board: &Board, params: &EvalParams) -> f32 {
+    let verdict = endgame_verdict(board, params);
+    if let Some(Verdict::Exact(_, score)) = verdict {
+        return score as f32 / 100.0;
+    }
+
+    let phase = game_phase(board);
+    let (mut mg, mut eg) = (board.psqt_mg, board.psqt_eg);
+
+    for [white, black] in [pawn_scores(board, params), material_scores(board, params)] {
+        mg += white.0 - black.0;
+        eg += white.1 - black.1;
+    }
+
+    let mut score = Score::new(params);
+    piece_features(board, &mut score);
+    let (pieces_mg, pieces_eg) = score.total();
+
+    let score = taper(mg + pieces_mg, eg + pieces_eg, phase);
+    scale(score, verdict) as f32 / 100.0
+}
+
+// Static evaluation in pawns from the point of view of the side to move, which is what a negamax
+// search wants. The tempo bonus already goes to whoever is to move.
+pub fn eval_relative(board: &Board, params: &EvalParams) -> f32 {
+    let score = eval(board, params);
+    if board.turn {
+        score
+    } else {
+        -score
+    }
+}
+
+// Applies endgame knowledge to a normal score in centipawns
+fn scale(score: i32, verdict: Option<Verdict>) -> i32 {
+    match verdict {
+        Some(Verdict::Exact(_, exact)) => exact,
+        Some(Verdict::Scale(_, scale)) => score * scale / NORMAL_SCALE,
+        None => score,
+    }
+}
+
+// Every feature eval scores, material and piece-square tables included
+pub fn eval_features(board: &Board, sink: &mut impl EvalSink) {
+    material_and_pst_features(board, sink);
+    pawn_features(board, sink);
+    imbalance_features(board, sink);
+    piece_features(board, sink);
+}
+
+// The features eval works out on every call rather than keeping in Board or a cache
+fn piece_features(board: &Board, sink: &mut impl EvalSink) {
+    let attacks = attack_maps(board);
+    for white in [true, false] {
+        king_safety(board, white, sink);
+        piece_activity(board, white, sink);
+        threats(board, white, &attacks, sink);
+        space(board, white, &attacks, sink);
+    }
+    sink.add(TEMPO, board.turn, 1);
+}
+
+// One line of an evaluation trace: a term for each side from that side's point of view, as
+// (middlegame, endgame)
+pub struct TraceTerm {
+    pub name: &'static str,
+    pub white: (i32, i32),
+    pub black: (i32, i32),
+}
+
+// The static evaluation broken down by term. The terms add up to what eval returns.
+pub struct EvalTrace {
+    pub terms: Vec<TraceTerm>,
+    pub phase: i32,
+    pub endgame: Option<Verdict>,
+}
+
+impl EvalTrace {
+    // White minus Black over every term, as (middlegame, endgame)
+    pub fn total(&self) -> (i32, i32) {
+        self.terms.iter().fold((0, 0), |(mg, eg), term| {
+            (
+                mg + term.white.0 - term.black.0,
+                eg + term.white.1 - term.black.1,
+            )
+        })
+    }
+
+    // The blended score in centipawns from White's point of view, after endgame knowledge
+    pub fn score(&self) -> i32 {
+        let (mg, eg) = self.total();
+        scale(taper(mg, eg, self.phase), self.endgame)
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            "term", "white mg", "white eg", "black mg", "black eg", "total mg", "total eg"
+        )?;
+        for term in self.terms.iter() {
+            writeln!(
+                f,
+                "{:<14}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+                term.name,
+                term.white.0,
+                term.white.1,
+                term.black.0,
+                term.black.1,
+                term.white.0 - term.black.0,
+                term.white.1 - term.black.1
+            )?;
+        }
+        let (mg, eg) = self.total();
+        writeln!(f, "{:<54}{:>10}{:>10}", "total", mg, eg)?;
+        match self.endgame {
+            Some(Verdict::Exact(name, _)) => writeln!(f, "replaced by the {name} evaluation")?,
+            Some(Verdict::Scale(name, scale)) => {
+                writeln!(f, "scaled by {scale}/{NORMAL_SCALE} for {name}")?
+            }
+            None => {}
+        }
+        write!(
+            f,
+            "phase {}/{MAX_PHASE}, final score {:+.2} (White's point of view)",
+            self.phase,
+            self.score() as f32 / 100.0
+        )
+    }
+}
+
+// Sorts each weight into the trace line of its group
+struct TraceSink<'a> {
+    params: &'a EvalParams,
+    trace: EvalTrace,
+}
+
+impl EvalSink for TraceSink<'_> {
+    fn add(&mut self, index: usize, white: bool, count: i32) {
+        let (mg, eg) = self.params.weights[index];
+        let term = &mut self.trace.terms[group_of(index).term];
+        let side = if white {
+            &mut term.white
+        } else {
+            &mut term.black
+        };
+        side.0 += count * mg;
+        side.1 += count * eg;
+    }
+}
+
+// Works out the same score as eval, keeping every term apart
+pub fn eval_trace(board: &Board, params: &EvalParams) -> EvalTrace {
+    let terms = TERMS
+        .iter()
+        .map(|&name| TraceTerm {
+            name,
+            white: (0, 0),
+            black: (0, 0),
+        })
+        .collect();
+    let mut sink = TraceSink {
+        params,
+        trace: EvalTrace {
+            terms,
+            phase: game_phase(board),
+            endgame: endgame_verdict(board, params),
+        },
+    };
+    eval_features(board, &mut sink);
+    sink.trace
+}
+
+// Each side's piece bitboards, indexed like the piece-square tables
+pub fn piece_boards(board: &Board) -> ([u64; 6], [u64; 6]) {
+    (
+        [
+            board.white_pawn,
+            board.white_knight,
+            board.white_bishop,
+            board.white_rook,
+            board.white_queen,
+            board.white_king,
+        ],
+        [
+            board.black_pawn,
+            board.black_knight,
+            board.black_bishop,
+            board.black_rook,
+            board.black_queen,
+            board.black_king,
+        ],
+    )
+}
+
+// Counts down from MAX_PHASE as pieces come off. Capped in case of promotions
+pub fn game_phase(board: &Board) -> i32 {
+    let (white, black) = piece_boards(board);
+    let mut phase = 0;
+    for piece in 0..6 {
+        let count = count_ones(white[piece]) + count_ones(black[piece]);
+        phase += PHASE_WEIGHTS[piece] * count as i32;
+    }
+    phase.min(MAX_PHASE)
+}
+
+// Blends a middlegame and an endgame score by the game phase
+pub fn taper(mg: i32, eg: i32, phase: i32) -> i32 {
+    (mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+// Material and piece-square score of one piece, signed so White's count positive
+pub fn piece_score(params: &EvalParams, piece: usize, square: u8, white: bool) -> (i32, i32) {
+    let square = if white { square } else { square ^ 56 };
+    let (value_mg, value_eg) = params.weights[PIECE_VALUE + piece];
+    let (pst_mg, pst_eg) = params.weights[PST + piece * 64 + square as usize];
+    let sign = if white { 1 } else { -1 };
+    (sign * (value_mg + pst_mg), sign * (value_eg + pst_eg))
+}
+
+// Material plus piece-square scores from scratch, White minus Black, as (middlegame, endgame).
+// make_move keeps the same numbers in Board up to date incrementally, with the same weights.
+pub fn material_and_pst(board: &Board) -> (i32, i32) {
+    with_params(|params| {
+        let mut score = Score::new(params);
+        material_and_pst_features(board, &mut score);
+        score.total()
+    })
+}
+
+fn material_and_pst_features(board: &Board, sink: &mut impl EvalSink) {
+    let (white, black) = piece_boards(board);
+    for piece in 0..6 {
+        for (pieces, colour) in [(white[piece], true), (black[piece], false)] {
+            for square in set_bit_positions(pieces) {
+                // Black reads the tables upside down
+                let square = if colour { square } else { square ^ 56 };
+                sink.add(PIECE_VALUE + piece, colour, 1);
+                sink.add(PST + piece * 64 + square as usize, colour, 1);
+            }
+        }
+    }
+}
+
+// Squares a knight, bishop, rook, queen or king on the square attacks
+fn piece_attacks(piece: usize, square: u8, occupied: u64) -> u64 {
+    match piece {
+        1 => KNIGHT_MOVE_MASKS[square as usize],
+        2 => bishop_attacks(square, occupied),
+        3 => rook_attacks(square, occupied),
+        4 => bishop_attacks(square, occupied) | rook_attacks(square, occupied),
+        _ => KING_MOVE_MASKS[square as usize],
+    }
+}
+
+// The squares each side attacks with each kind of piece, indexed like piece_boards, White's first
+pub fn attack_maps(board: &Board) -> [[u64; 6]; 2] {
+    let occupied = board.white | board.black;
+    let (white, black) = piece_boards(board);
+    let mut maps = [[0; 6]; 2];
+    for (side, pieces) in [white, black].into_iter().enumerate() {
+        maps[side][0] = pawn_attacks(pieces[0], side == 0);
+        for piece in 1..6 {
+            for square in set_bit_positions(pieces[piece]) {
+                maps[side][piece] |= piece_attacks(piece, square, occupied);
+            }
+        }
+    }
+    maps
+}
+
+// How safe the given side's king is
+fn king_safety(board: &Board, white: bool, sink: &mut impl EvalSink) {
+    let (own, enemy) = piece_boards(board);
+    let (own, enemy) = if white { (own, enemy) } else { (enemy, own) };
+    let king = own[5].trailing_zeros() as u8;
+    let file = king % 8;
+    let rank = king / 8;
+    let ahead = ranks_ahead(rank, white);
+
+    for shield_file in file.saturating_sub(1)..=(file + 1).min(7) {
+        let file_mask = FILES[shield_file as usize];
+
+        // distance to the nearest pawn in front of the king on this file
+        let nearest = |pawns: u64| -> Option<u8> {
+            let pawns = pawns & file_mask & ahead;
+            if pawns == 0 {
+                None
+            } else if white {
+                Some(pawns.trailing_zeros() as u8 / 8 - rank)
             } else {
-                return f32::INFINITY;
+                Some(rank - (63 - pawns.leading_zeros()) as u8 / 8)
             }
+        };
+        match nearest(own[0]) {
+            Some(distance) if distance < 3 => sink.add(SHIELD + distance as usize, white, 1),
+            _ => {}
+        }
+        match nearest(enemy[0]) {
+            Some(distance) if distance < 5 => sink.add(STORM + distance as usize, white, 1),
+            _ => {}
+        }
+
+        if own[0] & file_mask == 0 {
+            if enemy[0] & file_mask == 0 {
+                sink.add(OPEN_FILE_NEAR_KING, white, 1);
+            } else {
+                sink.add(SEMI_OPEN_FILE_NEAR_KING, white, 1);
+            }
+        }
+    }
+
+    // attack units: enemy pieces hitting the squares around the king
+    let zone = KING_MOVE_MASKS[king as usize] | 1 << king;
+    let occupied = board.white | board.black;
+    let mut attackers = 0;
+    let mut units = 0;
+    for piece in 1..5 {
+        for square in set_bit_positions(enemy[piece]) {
+            let attacks = piece_attacks(piece, square, occupied);
+            let hits = count_ones(attacks & zone) as i32;
+            if hits > 0 {
+                attackers += 1;
+                units += KING_ATTACK_WEIGHTS[piece - 1] * hits;
+            }
+        }
+    }
+    // a lone attacker rarely gets anywhere
+    if attackers >= 2 {
+        let units = (units as usize).min(KING_DANGER_SIZE - 1);
+        sink.add(KING_DANGER + units, white, 1);
+    }
+}
+
+// Mobility and placement of the given side's pieces
+fn piece_activity(board: &Board, white: bool, sink: &mut impl EvalSink) {
+    let (own, enemy) = piece_boards(board);
+    let (own, enemy) = if white { (own, enemy) } else { (enemy, own) };
+    let own_all = if white { board.white } else { board.black };
+    let occupied = board.white | board.black;
+    let enemy_pawn_attacks = pawn_attacks(enemy[0], !white);
+    let own_pawn_attacks = pawn_attacks(own[0], white);
+    // squares worth counting: not blocked by our own pieces, not covered by enemy pawns
+    let safe = !own_all & !enemy_pawn_attacks;
+    let relative_rank = |square: u8| if white { square / 8 } else { 7 - square / 8 };
+
+    for piece in 1..5 {
+        for square in set_bit_positions(own[piece]) {
+            let attacks = piece_attacks(piece, square, occupied);
+            let count = count_ones(attacks & safe) as i32 - MOBILITY_BASELINE[piece - 1];
+            sink.add(MOBILITY + piece - 1, white, count);
+
+            let file = square % 8;
+            match piece {
+                // outposts: defended by a pawn, on the enemy's side and beyond the reach of
+                // enemy pawns
+                1 => {
+                    let rank = relative_rank(square);
+                    let chasers = enemy[0] & adjacent_files(file) & ranks_ahead(square / 8, white);
+                    if (3..=5).contains(&rank)
+                        && own_pawn_attacks & (1 << square) != 0
+                        && chasers == 0
+                    {
+                        sink.add(KNIGHT_OUTPOST, white, 1);
+                    }
+                }
+                2 if is_trapped_bishop(square, white, enemy[0]) => {
+                    sink.add(TRAPPED_BISHOP, white, 1);
+                }
+                3 => {
+                    let file_mask = FILES[file as usize];
+                    if own[0] & file_mask == 0 {
+                        if enemy[0] & file_mask == 0 {
+                            sink.add(ROOK_OPEN_FILE, white, 1);
+                        } else {
+                            sink.add(ROOK_SEMI_OPEN_FILE, white, 1);
+                        }
+                    }
+
+                    // the seventh matters while it has pawns to eat or hems the king in
+                    let seventh = if white { SEVENTH_RANK } else { SECOND_RANK };
+                    let eighth = if white { EIGHTH_RANK } else { FIRST_RANK };
+                    if relative_rank(square) == 6
+                        && (enemy[0] & seventh != 0 || enemy[5] & eighth != 0)
+                    {
+                        sink.add(ROOK_ON_SEVENTH, white, 1);
+                    }
+
+                    // boxed in on the back rank by its own uncastled king
+                    let king = own[5].trailing_zeros() as u8;
+                    let king_file = king % 8;
+                    let boxed_in = relative_rank(square) == 0
+                        && relative_rank(king) == 0
+                        && ((king_file >= 4 && file > king_file)
+                            || (king_file < 4 && file < king_file));
+                    if boxed_in && count_ones(attacks & safe) <= 3 {
+                        sink.add(TRAPPED_ROOK, white, 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if count_ones(own[2]) >= 2 {
+        sink.add(BISHOP_PAIR, white, 1);
+    }
+}
+
+// Enemy pieces the given side attacks with something cheaper, enemy pieces it attacks that
+// nothing defends, and safe pawn pushes that would attack a piece
+fn threats(board: &Board, white: bool, attacks: &[[u64; 6]; 2], sink: &mut impl EvalSink) {
+    let (own, enemy) = piece_boards(board);
+    let (own, enemy) = if white { (own, enemy) } else { (enemy, own) };
+    let (ours, theirs) = if white {
+        (&attacks[0], &attacks[1])
+    } else {
+        (&attacks[1], &attacks[0])
+    };
+    let attacked = ours.iter().fold(0, |all, map| all | map);
+    let defended = theirs.iter().fold(0, |all, map| all | map);
+
+    // pawns are cheaper than everything, minors than rooks and queens, rooks than queens
+    for (victim, &pieces) in enemy[1..5].iter().enumerate() {
+        // victim counts from knights
+        let cheaper = match victim {
+            0 | 1 => ours[0],
+            2 => ours[0] | ours[1] | ours[2],
+            _ => ours[0] | ours[1] | ours[2] | ours[3],
+        };
+        let count = count_ones(pieces & cheaper) as i32;
+        if count > 0 {
+            sink.add(THREAT_BY_LESSER + victim, white, count);
+        }
+    }
+
+    let enemy_all = if white { board.black } else { board.white };
+    let hanging = count_ones(enemy_all & !enemy[5] & attacked & !defended) as i32;
+    if hanging > 0 {
+        sink.add(HANGING, white, hanging);
+    }
+
+    // pushes, double ones too, to squares enemy pawns don't cover and that are defended if
+    // attacked at all
+    let empty = !(board.white | board.black);
+    let (single, double) = if white {
+        let single = (own[0] << 8) & empty;
+        (single, ((single & THIRD_RANK) << 8) & empty)
+    } else {
+        let single = (own[0] >> 8) & empty;
+        (single, ((single & SIXTH_RANK) >> 8) & empty)
+    };
+    let pushes = (single | double) & !theirs[0] & (attacked | !defended);
+    let targets = enemy[1] | enemy[2] | enemy[3] | enemy[4];
+    let threatened = count_ones(pawn_attacks(pushes, white) & targets & !ours[0]) as i32;
+    if threatened > 0 {
+        sink.add(PAWN_PUSH_THREAT, white, threatened);
+    }
+}
+
+// Squares in the side's own half that enemy pawns don't attack, with those behind its pawns
+// counted twice, and how many of its pieces attack the centre
+fn space(board: &Board, white: bool, attacks: &[[u64; 6]; 2], sink: &mut impl EvalSink) {
+    let (side, own_pawns) = if white {
+        (0, board.white_pawn)
+    } else {
+        (1, board.black_pawn)
+    };
+    let theirs = &attacks[1 - side];
+    let safe = SPACE_ZONES[side] & !own_pawns & !theirs[0];
+    // up to three squares behind each pawn
+    let behind = if white {
+        (own_pawns >> 8) | (own_pawns >> 16) | (own_pawns >> 24)
+    } else {
+        (own_pawns << 8) | (own_pawns << 16) | (own_pawns << 24)
+    };
+    let count = count_ones(safe) as i32 + count_ones(safe & behind) as i32;
+    if count > 0 {
+        sink.add(SPACE, white, count);
+    }
+
+    let control: i32 = attacks[side]
+        .iter()
+        .map(|map| count_ones(map & CENTRE) as i32)
+        .sum();
+    if control > 0 {
+        sink.add(CENTRE_CONTROL, white, control);
+    }
+}
+
+// The middlegame score the given side gets for space and the centre, for ordering moves
+pub fn space_score(board: &Board, white: bool, params: &EvalParams) -> i32 {
+    let mut score = Score::new(params);
+    space(board, white, &attack_maps(board), &mut score);
+    score.sides[if white { 0 } else { 1 }].0
+}
+
+// A bishop that took the a7 or h7 pawn and is about to be shut in by b6 or g6
+fn is_trapped_bishop(square: u8, white: bool, enemy_pawns: u64) -> bool {
+    let patterns: [(u8, u8); 4] = if white {
+        [(48, 41), (55, 46), (40, 33), (47, 38)]
+    } else {
+        [(8, 17), (15, 22), (16, 25), (23, 30)]
+    };
+    patterns
+        .iter()
+        .any(|&(bishop, pawn)| square == bishop && enemy_pawns & (1 << pawn) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::zobrist_hash;
+
+    // walks every line to the given depth checking the incremental scores against a recount
+    fn check_scores(board: &Board, depth: u8) {
+        assert_eq!((board.psqt_mg, board.psqt_eg), material_and_pst(board));
+        if depth == 0 {
+            return;
+        }
+        for ply in legal_moves(board) {
+            check_scores(&make_move(board, &ply), depth - 1);
         }
-        return 0.0;
-    }
-    let mut evaluation = 0.0;
-
-    evaluation += count_ones(board.white_pawn) as f32;
-    evaluation += 3.0 * count_ones(board.white_knight) as f32;
-    evaluation += 3.2 * count_ones(board.white_bishop) as f32;
-    evaluation += 5.0 * count_ones(board.white_rook) as f32;
-    evaluation += 9.0 * count_ones(board.white_queen) as f32;
-    evaluation -= count_ones(board.black_pawn) as f32;
-    evaluation -= 3.0 * count_ones(board.black_knight) as f32;
-    evaluation -= 3.2 * count_ones(board.black_bishop) as f32;
-    evaluation -= 5.0 * count_ones(board.black_rook) as f32;
-    evaluation -= 9.0 * count_ones(board.black_queen) as f32;
-
-    evaluation
-}
-/*
-* white_pawn = 0
-* white_knight = 1
-* white_bishop = 2
-* white_rook = 3
-* white_queen = 4
-* white_king = 5
-* black_pawn = 6
-* black_knight = 7
-* black_bishop = 8
-* black_rook = 9
-* black_queen = 10
-* black_king = 11
-*/
+    }
+
+    #[test]
+    fn incremental_scores() {
+        // castling, en passant and captures
+        check_scores(
+            &fen_to_board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+            2,
+        );
+        // promotions with and without captures
+        check_scores(&fen_to_board("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1"), 3);
+    }
+
+    #[test]
+    fn eval_cache() {
+        clear_eval_cache();
+        let key = zobrist_hash(&starting_position());
+        assert_eq!(probe_eval_cache(key), None);
+        store_eval_cache(key, 0.25);
+        assert_eq!(probe_eval_cache(key), Some(0.25));
+        // a different position landing on the same entry replaces it
+        store_eval_cache(key ^ 1 << 63, -1.0);
+        assert_eq!(probe_eval_cache(key), None);
+        assert_eq!(probe_eval_cache(key ^ 1 << 63), Some(-1.0));
+        clear_eval_cache();
+        assert_eq!(probe_eval_cache(key ^ 1 << 63), None);
+    }
+
+    #[test]
+    fn start_is_level() {
+        let board = starting_position();
+        assert_eq!(game_phase(&board), MAX_PHASE);
+        // only the move separates the sides
+        let params = params();
+        let tempo = params.weights[TEMPO].0 as f32 / 100.0;
+        assert_eq!(eval(&board, &params), tempo);
+        assert_eq!(eval_relative(&board, &params), tempo);
+        assert_eq!(eval_relative(&mirror(&board), &params), tempo);
+    }
+
+    #[test]
+    fn knight_on_the_rim() {
+        let params = params();
+        // pawns so it isn't a dead draw
+        let rim = fen_to_board("4k3/7p/8/8/N7/8/7P/4K3 w - - 0 1");
+        let centre = fen_to_board("4k3/7p/8/8/3N4/8/7P/4K3 w - - 0 1");
+        assert!(eval(&centre, &params) > eval(&rim, &params));
+    }
+
+    #[test]
+    fn pawn_shield() {
+        let sheltered = fen_to_board("r5k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let pushed = fen_to_board("r5k1/5ppp/8/8/6PP/5P2/8/R5K1 w - - 0 1");
+        assert!(safety(&sheltered, true) > safety(&pushed, true));
+        assert_eq!(safety(&sheltered, true), safety(&sheltered, false));
+    }
+
+    #[test]
+    fn open_file_and_attackers() {
+        let closed = fen_to_board("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let open = fen_to_board("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert!(safety(&closed, true) > safety(&open, true));
+
+        let attacked = fen_to_board("6k1/5ppp/8/8/8/5n2/5PPq/6K1 w - - 0 1");
+        assert!(safety(&attacked, true) < safety(&closed, true) - 50);
+    }
+
+    // middlegame king safety score for one side
+    fn safety(board: &Board, white: bool) -> i32 {
+        let params = EvalParams::default();
+        let mut score = Score::new(&params);
+        king_safety(board, white, &mut score);
+        score.sides[if white { 0 } else { 1 }].0
+    }
+
+    // White's mobility and placement
+    fn activity(board: &Board) -> (i32, i32) {
+        let params = EvalParams::default();
+        let mut score = Score::new(&params);
+        piece_activity(board, true, &mut score);
+        score.sides[0]
+    }
+
+    #[test]
+    fn activity_terms() {
+        let pair = fen_to_board("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        let no_pair = fen_to_board("4k3/8/8/8/8/8/8/2N1KB2 w - - 0 1");
+        assert!(activity(&pair).1 - activity(&no_pair).1 >= 50);
+
+        let open = fen_to_board("4k3/pp4pp/8/8/8/8/PP4PP/3RK3 w - - 0 1");
+        let closed = fen_to_board("4k3/pp1p2pp/8/8/8/8/PP1P2PP/3RK3 w - - 0 1");
+        assert!(activity(&open).0 > activity(&closed).0);
+
+        // the f7 pawn can drive the knight away, and it also takes g6 from it
+        let outpost = fen_to_board("4k3/8/8/4N3/3P4/8/8/4K3 w - - 0 1");
+        let chased = fen_to_board("4k3/5p2/8/4N3/3P4/8/8/4K3 w - - 0 1");
+        let params = EvalParams::default();
+        assert_eq!(
+            activity(&outpost).0 - activity(&chased).0,
+            params.weights[KNIGHT_OUTPOST].0 + params.weights[MOBILITY].0
+        );
+    }
+
+    // White's threats, as (middlegame, endgame)
+    fn threat_score(board: &Board) -> (i32, i32) {
+        let params = EvalParams::default();
+        let mut score = Score::new(&params);
+        threats(board, true, &attack_maps(board), &mut score);
+        score.sides[0]
+    }
+
+    #[test]
+    fn threat_terms() {
+        let params = EvalParams::default();
+        // the pawn on d4 hits a knight that the e6 pawn defends
+        let knight = fen_to_board("4k3/8/4p3/3n4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(threat_score(&knight), params.weights[THREAT_BY_LESSER]);
+
+        // a rook attacked by nothing cheaper, but not defended either
+        let hanging = fen_to_board("4k3/8/8/3r4/8/8/3Q4/4K3 w - - 0 1");
+        assert_eq!(threat_score(&hanging), params.weights[HANGING]);
+
+        // e4-e5 would hit the knight on d6, and e5 is defended by the d4 pawn
+        let push = fen_to_board("4k3/8/3n4/8/3PP3/8/8/4K3 w - - 0 1");
+        let no_push = fen_to_board("4k3/8/3n1p2/4p3/3PP3/8/8/4K3 w - - 0 1");
+        assert_eq!(threat_score(&push), params.weights[PAWN_PUSH_THREAT]);
+        assert_eq!(threat_score(&no_push).0, 0);
+    }
+
+    #[test]
+    fn space_and_centre() {
+        let params = params();
+        let start = starting_position();
+        assert_eq!(
+            space_score(&start, true, &params),
+            space_score(&start, false, &params)
+        );
+        // e4 and d4 take space and hit the centre, then the e5 pawn takes f4 away and contests d4
+        let centre = fen_to_board("rnbqkbnr/pppppppp/8/8/3PP3/8/PPP2PPP/RNBQKBNR b KQkq - 0 2");
+        assert!(space_score(&centre, true, &params) > space_score(&start, true, &params));
+        let challenged =
+            fen_to_board("rnbqkbnr/pppp1ppp/8/4p3/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3");
+        assert!(space_score(&challenged, true, &params) < space_score(&centre, true, &params));
+        assert!(space_score(&challenged, false, &params) > space_score(&centre, false, &params));
+    }
+
+    #[test]
+    fn trace_adds_up() {
+        let params = params();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "6k1/5ppp/8/8/8/5n2/5PPq/6K1 w - - 0 1",
+            "4k3/p7/8/8/3K4/8/P7/8 w - - 0 1",
+        ] {
+            let board = fen_to_board(fen);
+            let trace = eval_trace(&board, &params);
+            assert_eq!(trace.score() as f32 / 100.0, eval(&board, &params));
+        }
+    }
+
+    // Every term that scores differently once the position is mirrored, by name
+    fn asymmetric_terms(board: &Board) -> Vec<&'static str> {
+        let params = params();
+        let trace = eval_trace(board, &params);
+        let mirrored = eval_trace(&mirror(board), &params);
+        let mut broken: Vec<&'static str> = trace
+            .terms
+            .iter()
+            .zip(&mirrored.terms)
+            .filter(|(term, other)| term.white != other.black || term.black != other.white)
+            .map(|(term, _)| term.name)
+            .collect();
+        if eval(board, &params) != -eval(&mirror(board), &params) && broken.is_empty() {
+            broken.push("endgame scaling");
+        }
+        broken
+    }
+
+    // walks every line to the given depth, noting the moves that lead to asymmetric scores
+    fn check_symmetry(
+        board: &Board,
+        depth: u8,
+        line: &mut Vec<String>,
+        failures: &mut Vec<String>,
+    ) {
+        let broken = asymmetric_terms(board);
+        if !broken.is_empty() {
+            failures.push(format!("after {}: {}", line.join(" "), broken.join(", ")));
+        }
+        if depth == 0 {
+            return;
+        }
+        for ply in legal_moves(board) {
+            line.push(move_to_uci(&ply));
+            check_symmetry(&make_move(board, &ply), depth - 1, line, failures);
+            line.pop();
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "2r3k1/1q3ppp/p3p3/1p1nP3/3P4/P2B1Q2/1P3PPP/2R3K1 b - - 0 25",
+            "8/2k5/3p4/p2P1p2/P4P2/4K3/8/8 w - - 0 40",
+            "8/5pk1/6p1/8/8/4B1P1/5PK1/3b4 w - - 0 50",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "6k1/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+        ] {
+            let mut failures = Vec::new();
+            check_symmetry(&fen_to_board(fen), 2, &mut Vec::new(), &mut failures);
+            assert!(failures.is_empty(), "{fen}\n{}", failures.join("\n"));
+        }
+    }
+
+    #[test]
+    fn trapped_bishop() {
+        let board = fen_to_board("4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1");
+        assert!(is_trapped_bishop(48, true, board.black_pawn));
+        let mirrored = fen_to_board("4k3/8/8/8/8/1P6/b7/4K3 b - - 0 1");
+        assert!(is_trapped_bishop(8, false, mirrored.white_pawn));
+    }
+
+    #[test]
+    fn king_centralises_in_endgame() {
+        let params = params();
+        let corner = fen_to_board("4k3/p7/8/8/8/8/P7/K7 w - - 0 1");
+        let centre = fen_to_board("4k3/p7/8/8/3K4/8/P7/8 w - - 0 1");
+        assert_eq!(game_phase(&centre), 0);
+        assert!(eval(&centre, &params) > eval(&corner, &params));
+    }
+}
@@ -106,3 +106,35 @@ pub fn zobrist_hash(board: &Board) -> u64 {
 
     hash
 }
+
+// Hashes only the pawns, with the same keys as zobrist_hash. Keys the pawn structure cache
+pub fn pawn_hash(board: &Board) -> u64 {
+    let mut hash: u64 = 0;
+    for white_pawn in set_bit_positions(board.white_pawn) {
+        hash ^= ZOBRIST_KEYS[white_pawn as usize];
+    }
+    for black_pawn in set_bit_positions(board.black_pawn) {
+        hash ^= ZOBRIST_KEYS[384 + black_pawn as usize];
+    }
+    hash
+}
+
+// Piece counts four bits each, pawns to queens, White's then Black's. Every material
+// configuration gets its own key, so the material cache never mixes two up.
+pub fn material_key(board: &Board) -> u64 {
+    let counts = [
+        board.white_pawn,
+        board.white_knight,
+        board.white_bishop,
+        board.white_rook,
+        board.white_queen,
+        board.black_pawn,
+        board.black_knight,
+        board.black_bishop,
+        board.black_rook,
+        board.black_queen,
+    ];
+    counts
+        .iter()
+        .fold(0, |key, pieces| key << 4 | pieces.count_ones() as u64)
+}
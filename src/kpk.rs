@@ -0,0 +1,225 @@
+use crate::utils::*;
+use lazy_static::lazy_static;
+
+// King and pawn against king, solved at startup by retrograde analysis. Positions are seen with
+// the pawn White's and on the a to d files; anything else is flipped to match. An entry is
+// indexed by the side to move, both king squares and the pawn square, and one bit records
+// whether White wins.
+const PAWN_SQUARES: usize = 24;
+const POSITIONS: usize = 2 * 64 * 64 * PAWN_SQUARES;
+
+lazy_static! {
+    pub static ref KPK_BITBASE: Vec<u64> = generate();
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Result {
+    Invalid,
+    Unknown,
+    Draw,
+    Win,
+}
+
+fn index(white_to_move: bool, white_king: u8, black_king: u8, pawn: u8) -> usize {
+    // pawns stand on ranks 2 to 7 and files a to d
+    let pawn = (pawn / 8 - 1) as usize * 4 + (pawn % 8) as usize;
+    (((pawn * 64 + white_king as usize) * 64 + black_king as usize) * 2) + !white_to_move as usize
+}
+
+fn distance(a: u8, b: u8) -> u8 {
+    let files = (a % 8).abs_diff(b % 8);
+    let ranks = (a / 8).abs_diff(b / 8);
+    files.max(ranks)
+}
+
+fn pawn_attacks(pawn: u8) -> u64 {
+    let mut attacks = 0;
+    if !pawn.is_multiple_of(8) {
+        attacks |= 1 << (pawn + 7);
+    }
+    if pawn % 8 < 7 {
+        attacks |= 1 << (pawn + 9);
+    }
+    attacks
+}
+
+// What is clear without looking at any moves
+fn initial(white_to_move: bool, white_king: u8, black_king: u8, pawn: u8) -> Result {
+    if distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || (white_to_move && pawn_attacks(pawn) & (1 << black_king) != 0)
+    {
+        return Result::Invalid;
+    }
+
+    if white_to_move {
+        // the pawn promotes and the new queen can't be taken
+        let promotion = pawn + 8;
+        if pawn / 8 == 6
+            && white_king != promotion
+            && black_king != promotion
+            && (distance(black_king, promotion) > 1 || distance(white_king, promotion) == 1)
+        {
+            return Result::Win;
+        }
+    } else {
+        let guarded = KING_MOVE_MASKS[white_king as usize] | pawn_attacks(pawn);
+        // stalemate, or the pawn can be taken
+        if KING_MOVE_MASKS[black_king as usize] & !guarded == 0 {
+            return Result::Draw;
+        }
+        if KING_MOVE_MASKS[black_king as usize] & !guarded & (1 << pawn) != 0 {
+            return Result::Draw;
+        }
+    }
+
+    Result::Unknown
+}
+
+// White needs one winning move. Black needs one drawing move.
+fn classify(
+    results: &[Result],
+    white_to_move: bool,
+    white_king: u8,
+    black_king: u8,
+    pawn: u8,
+) -> Result {
+    let mut children = Vec::new();
+    if white_to_move {
+        for to in set_bit_positions(KING_MOVE_MASKS[white_king as usize]) {
+            if to != pawn && distance(to, black_king) > 1 {
+                children.push(index(false, to, black_king, pawn));
+            }
+        }
+        // promotions that win are found by initial
+        let push = pawn + 8;
+        if pawn / 8 < 6 && push != white_king && push != black_king {
+            children.push(index(false, white_king, black_king, push));
+            let double = push + 8;
+            if pawn / 8 == 1 && double != white_king && double != black_king {
+                children.push(index(false, white_king, black_king, double));
+            }
+        }
+    } else {
+        let guarded = KING_MOVE_MASKS[white_king as usize] | pawn_attacks(pawn) | 1 << pawn;
+        for to in set_bit_positions(KING_MOVE_MASKS[black_king as usize] & !guarded) {
+            children.push(index(true, white_king, to, pawn));
+        }
+    }
+
+    let (good, bad) = if white_to_move {
+        (Result::Win, Result::Draw)
+    } else {
+        (Result::Draw, Result::Win)
+    };
+    if children.iter().any(|&child| results[child] == good) {
+        good
+    } else if children.iter().all(|&child| results[child] == bad) {
+        bad
+    } else {
+        Result::Unknown
+    }
+}
+
+fn generate() -> Vec<u64> {
+    let mut results = vec![Result::Invalid; POSITIONS];
+    let mut positions = Vec::new();
+    for pawn_index in 0..PAWN_SQUARES as u8 {
+        let pawn = (pawn_index / 4 + 1) * 8 + pawn_index % 4;
+        for white_king in 0..64 {
+            for black_king in 0..64 {
+                for white_to_move in [true, false] {
+                    let i = index(white_to_move, white_king, black_king, pawn);
+                    results[i] = initial(white_to_move, white_king, black_king, pawn);
+                    if results[i] == Result::Unknown {
+                        positions.push((i, white_to_move, white_king, black_king, pawn));
+                    }
+                }
+            }
+        }
+    }
+
+    // keep going over the undecided positions until a pass settles nothing new
+    let mut changed = true;
+    while changed {
+        changed = false;
+        positions.retain(|&(i, white_to_move, white_king, black_king, pawn)| {
+            let result = classify(&results, white_to_move, white_king, black_king, pawn);
+            if result == Result::Unknown {
+                return true;
+            }
+            results[i] = result;
+            changed = true;
+            false
+        });
+    }
+
+    // whatever is still undecided can't be forced, so it's a draw
+    let mut bits = vec![0u64; POSITIONS / 64];
+    for (i, &result) in results.iter().enumerate() {
+        if result == Result::Win {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bits
+}
+
+// Whether the side with the pawn wins. Squares are as on the board, whichever side has the pawn.
+pub fn probe_kpk(strong_king: u8, weak_king: u8, pawn: u8, strong_white: bool, turn: bool) -> bool {
+    // see it from White's side with the pawn on the queenside
+    let flip_ranks = if strong_white { 0 } else { 56 };
+    let flip_files = if pawn % 8 > 3 { 7 } else { 0 };
+    let flip = |square: u8| square ^ flip_ranks ^ flip_files;
+    let strong_to_move = turn == strong_white;
+
+    let i = index(
+        strong_to_move,
+        flip(strong_king),
+        flip(weak_king),
+        flip(pawn),
+    );
+    KPK_BITBASE[i / 64] & (1 << (i % 64)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(fen: &str) -> bool {
+        let board = fen_to_board(fen);
+        let strong_white = board.white_pawn != 0;
+        let (strong, weak, pawn) = if strong_white {
+            (board.white_king, board.black_king, board.white_pawn)
+        } else {
+            (board.black_king, board.white_king, board.black_pawn)
+        };
+        probe_kpk(
+            strong.trailing_zeros() as u8,
+            weak.trailing_zeros() as u8,
+            pawn.trailing_zeros() as u8,
+            strong_white,
+            board.turn,
+        )
+    }
+
+    #[test]
+    fn opposition() {
+        // whoever has to move gives way
+        assert!(!probe("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"));
+        assert!(probe("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1"));
+        // the same positions with colours reversed and the pawn on the other wing
+        assert!(!probe("8/8/8/3p4/3k4/8/3K4/8 b - - 0 1"));
+        assert!(probe("8/8/8/3p4/3k4/8/3K4/8 w - - 0 1"));
+    }
+
+    #[test]
+    fn rook_pawn_and_runaway() {
+        // the defending king in front of a rook pawn can't be shifted
+        assert!(!probe("k7/8/8/8/8/8/P7/K7 w - - 0 1"));
+        assert!(!probe("7k/8/8/8/8/8/6KP/8 w - - 0 1"));
+        // the black king is outside the square of the pawn, unless it gets to move first
+        assert!(probe("k7/8/8/4P3/8/8/8/K7 w - - 0 1"));
+        assert!(!probe("k7/8/8/4P3/8/8/8/K7 b - - 0 1"));
+    }
+}
@@ -1,19 +1,41 @@
 #![allow(unused)]
 
 mod bitboard;
+mod endgame;
 mod eval;
 mod hash;
+mod kpk;
+mod mate;
+mod material;
 mod movegen;
+mod nnue;
+mod params;
+mod pawns;
 mod perft;
+mod pgn;
 mod play;
+mod pst;
 mod search;
+mod selfplay;
+mod syzygy;
+mod tune;
 mod utils;
+use crate::eval::{
+    clear_eval_cache, eval_trace, material_and_pst, set_eval_cache_size, DEFAULT_EVAL_CACHE_MB,
+};
+use crate::hash::zobrist_hash;
+use crate::mate::mate_search;
+use crate::nnue::Network;
+use crate::params::{set_params, EvalParams};
 use crate::play::*;
 use crate::search::*;
+use crate::syzygy::Tablebases;
 use crate::utils::*;
 use regex::Regex;
 use std::collections::HashMap;
+use std::fs;
 use std::io::{stdin, stdout, Write};
+use std::sync::Arc;
 use std::thread;
 
 fn main() {
@@ -24,9 +46,25 @@ fn main() {
     init_handle = Some(thread::spawn(|| {
         let _ = &*bitboard::MAGIC_TABLES;
         let _ = &*hash::ZOBRIST_KEYS;
+        let _ = &*kpk::KPK_BITBASE;
     }));
 
     let mut board = starting_position();
+    // hashes of the positions before the current one, for repetition detection
+    let mut history: Vec<u64> = Vec::new();
+    let mut options = SearchOptions::default();
+    let mut last_stats: Option<SearchStats> = None;
+    let setoption = Regex::new(r"setoption name (.+) value (.+)").unwrap();
+    let position = Regex::new(r"position (startpos|.+) moves (.*)").unwrap();
+
+    // --evalfile PATH starts with the weights from a parameter file
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--evalfile") {
+        if let Some(path) = args.get(i + 1) {
+            load_eval_file(path, &mut board);
+        }
+    }
+
     loop {
         let mut command_string = String::new();
         stdin()
@@ -40,6 +78,15 @@ fn main() {
         if command == "uci" {
             println!("id name Ike");
             println!("id author Ezra S-I");
+            println!("option name Contempt type spin default 0 min -100 max 100");
+            println!("option name UseNNUE type check default false");
+            println!("option name NNUEFile type string default <empty>");
+            println!("option name EvalFile type string default <empty>");
+            println!("option name SyzygyPath type string default <empty>");
+            println!(
+                "option name EvalCache type spin default {} min 0 max 1024",
+                DEFAULT_EVAL_CACHE_MB
+            );
 
             println!("uciok");
         }
@@ -49,27 +96,287 @@ fn main() {
             }
             println!("readyok");
         }
+        if command == "ucinewgame" {
+            clear_eval_cache();
+        }
 
         //finish implementing the actual position command. remove line in play command
-        let re = Regex::new(r"position (startpos|.+) moves (.*)").unwrap();
-        if let Some(captures) = re.captures(command) {
+        if let Some(captures) = position.captures(command) {
+            // replay the whole game so repetitions can be found
+            board = starting_position();
+            history.clear();
             let move_list = &captures[2];
             for mv in move_list.split_whitespace() {
+                history.push(zobrist_hash(&board));
                 make_user_move(mv, &mut board);
             }
             print_binary_board(board.white | board.black);
         }
 
-        if command == "go" {
-            let (best, eval) = best_move(&board, 1);
+        if let Some(captures) = setoption.captures(command) {
+            if &captures[1] == "Contempt" {
+                if let Ok(value) = captures[2].parse::<i32>() {
+                    options.contempt = value.clamp(-100, 100);
+                }
+            }
+            if &captures[1] == "EvalCache" {
+                if let Ok(megabytes) = captures[2].parse::<usize>() {
+                    set_eval_cache_size(megabytes.min(1024));
+                }
+            }
+            // cached scores come from whichever evaluation was in use
+            if &captures[1] == "UseNNUE" {
+                options.use_nnue = &captures[2] == "true";
+                clear_eval_cache();
+            }
+            if &captures[1] == "NNUEFile" {
+                match Network::load(&captures[2]) {
+                    Ok(network) => {
+                        println!("info string loaded {:?}", network);
+                        options.network = Some(Arc::new(network));
+                        clear_eval_cache();
+                    }
+                    Err(error) => println!("info string could not load network: {error}"),
+                }
+            }
+            if &captures[1] == "EvalFile" {
+                load_eval_file(&captures[2], &mut board);
+            }
+            if &captures[1] == "SyzygyPath" {
+                if &captures[2] == "<empty>" {
+                    options.tablebases = None;
+                } else {
+                    let tablebases = Tablebases::open(&captures[2]);
+                    println!(
+                        "info string found {} tablebases with up to {} pieces",
+                        tablebases.len(),
+                        tablebases.max_pieces
+                    );
+                    options.tablebases = Some(Arc::new(tablebases));
+                }
+            }
+            if options.use_nnue && options.network.is_none() {
+                println!("info string no network loaded, using the handcrafted eval");
+            }
+        }
+
+        if command.starts_with("go") {
+            let mut depth = 1;
+            let mut mate = None;
+            let mut tokens = command.split_whitespace();
+            while let Some(token) = tokens.next() {
+                if token == "depth" {
+                    if let Some(value) = tokens.next().and_then(|d| d.parse::<u8>().ok()) {
+                        depth = value.max(1);
+                    }
+                }
+                if token == "mate" {
+                    mate = tokens.next().and_then(|n| n.parse::<u8>().ok());
+                }
+            }
+
+            if let Some(moves) = mate {
+                if let Some(line) = mate_search(&board, moves) {
+                    let pv: Vec<String> = line.iter().map(move_to_uci).collect();
+                    println!(
+                        "info depth {} score mate {} pv {}",
+                        line.len(),
+                        line.len().div_ceil(2),
+                        pv.join(" ")
+                    );
+                    println!("{:?}", line.first());
+                } else {
+                    println!("info string no forced mate in {moves}");
+                    println!("{:?}", None::<Move>);
+                }
+                continue;
+            }
+
+            let (best, eval, line, stats) = best_move(&board, depth, &history, &options);
+            let pv: Vec<String> = line.iter().map(move_to_uci).collect();
+            println!(
+                "info depth {depth} score {} nodes {} pv {}",
+                uci_score(eval, board.turn, line.len()),
+                stats.nodes,
+                pv.join(" ")
+            );
             println!("{:?}", best);
             println!("evaluation: {eval}");
+            last_stats = Some(stats);
+        }
+
+        // static evaluation of the current position, term by term
+        if command == "eval" {
+            println!("{}", eval_trace(&board, &params::params()));
+            if let (true, Some(network)) = (options.use_nnue, &options.network) {
+                let score = network.evaluate(&network.refresh(&board), board.turn);
+                println!(
+                    "nnue score {:+.2} (White's point of view)",
+                    score as f32 / 100.0
+                );
+            }
+        }
+
+        // tune <dataset> [epochs N] [rate R] [out PATH]: fits the eval weights to the dataset's
+        // results and writes them out, as Rust source if PATH ends in .rs and as a parameter file
+        // otherwise
+        if command.starts_with("tune ") {
+            let mut tokens = command.split_whitespace().skip(1);
+            let dataset = tokens.next().unwrap_or_default().to_string();
+            let (mut epochs, mut rate, mut out) = (500, 1.0, "tuned.rs".to_string());
+            while let Some(token) = tokens.next() {
+                match (token, tokens.next()) {
+                    ("epochs", Some(value)) => epochs = value.parse().unwrap_or(epochs),
+                    ("rate", Some(value)) => rate = value.parse().unwrap_or(rate),
+                    ("out", Some(value)) => out = value.to_string(),
+                    _ => {}
+                }
+            }
+
+            match tune::load_samples(&dataset) {
+                Ok(samples) if !samples.is_empty() => {
+                    let tuned = tune::tune(&samples, &params::params(), epochs, rate);
+                    let written = if out.ends_with(".rs") {
+                        tune::write_rust(&tuned, &out)
+                    } else {
+                        fs::write(&out, tuned.to_text())
+                    };
+                    match written {
+                        Ok(()) => println!("wrote {out}"),
+                        Err(error) => println!("could not write {out}: {error}"),
+                    }
+                }
+                Ok(_) => println!("no positions in {dataset}"),
+                Err(error) => println!("could not read {dataset}: {error}"),
+            }
+        }
+
+        // extract <pgn>... [out PATH] [skip N] [rate R]: writes the quiet positions of the games in
+        // the PGN files with their results, in the format tune reads. The first N plies of each
+        // game are left out and each position is kept with probability R
+        if command.starts_with("extract ") {
+            let mut tokens = command.split_whitespace().skip(1);
+            let mut paths = Vec::new();
+            let mut out = "positions.txt".to_string();
+            let mut options = pgn::ExtractOptions::default();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "out" => out = tokens.next().unwrap_or(&out).to_string(),
+                    "skip" => {
+                        let value = tokens.next().and_then(|n| n.parse().ok());
+                        options.skip_plies = value.unwrap_or(options.skip_plies);
+                    }
+                    "rate" => {
+                        let value = tokens.next().and_then(|r| r.parse().ok());
+                        options.sample_rate = value.unwrap_or(options.sample_rate);
+                    }
+                    path => paths.push(path.to_string()),
+                }
+            }
+
+            let mut games = Vec::new();
+            for path in paths.iter() {
+                // PGN files in the wild aren't always UTF-8
+                match fs::read(path) {
+                    Ok(bytes) => games.extend(pgn::parse_games(&String::from_utf8_lossy(&bytes))),
+                    Err(error) => println!("could not read {path}: {error}"),
+                }
+            }
+            let lines = pgn::extract_positions(&games, &options);
+            let text: String = lines.iter().map(|line| format!("{line}\n")).collect();
+            match fs::write(&out, text) {
+                Ok(()) => println!(
+                    "wrote {} positions from {} games to {out}",
+                    lines.len(),
+                    games.len()
+                ),
+                Err(error) => println!("could not write {out}: {error}"),
+            }
+        }
+
+        // selfplay <games> [threads N] [nodes N] [random N] [out PREFIX]: plays games against
+        // itself with N nodes a move after N random plies, and writes each quiet position with
+        // its score and result to PREFIX.bin and, readable, PREFIX.txt
+        if command.starts_with("selfplay ") {
+            let mut tokens = command.split_whitespace().skip(1);
+            let mut selfplay = selfplay::SelfPlayOptions {
+                search: options.clone(),
+                ..selfplay::SelfPlayOptions::default()
+            };
+            selfplay.games = tokens.next().and_then(|n| n.parse().ok()).unwrap_or(selfplay.games);
+            let mut out = "selfplay".to_string();
+            while let Some(token) = tokens.next() {
+                let value = tokens.next();
+                let number = value.and_then(|n| n.parse().ok());
+                match token {
+                    "threads" => selfplay.threads = number.unwrap_or(selfplay.threads),
+                    "nodes" => selfplay.nodes = number.map_or(selfplay.nodes, |n| n as u64),
+                    "random" => selfplay.random_plies = number.unwrap_or(selfplay.random_plies),
+                    "out" => out = value.unwrap_or(&out).to_string(),
+                    _ => {}
+                }
+            }
+
+            let (binary, text) = (format!("{out}.bin"), format!("{out}.txt"));
+            match selfplay::generate(&selfplay, &binary, &text) {
+                Ok(positions) => println!("wrote {positions} positions to {binary} and {text}"),
+                Err(error) => println!("could not write {out}: {error}"),
+            }
+        }
+
+        // dumpparams [PATH]: writes the weights in use as a parameter file, or prints them
+        if command == "dumpparams" || command.starts_with("dumpparams ") {
+            let text = params::params().to_text();
+            match command.split_whitespace().nth(1) {
+                Some(path) => match fs::write(path, text) {
+                    Ok(()) => println!("wrote {path}"),
+                    Err(error) => println!("could not write {path}: {error}"),
+                },
+                None => print!("{text}"),
+            }
+        }
+
+        // debug report on the last search
+        if command == "stats" {
+            match &last_stats {
+                Some(stats) => println!("{stats}"),
+                None => println!("no search yet"),
+            }
         }
 
         if command == "play" {
             // remove following line when implemented position command
             board = starting_position();
+            history.clear();
             play_game(&board);
         }
     }
 }
+
+// Switches to the weights in a parameter file and recounts the current position's scores with them
+fn load_eval_file(path: &str, board: &mut Board) {
+    match EvalParams::load(path) {
+        Ok(params) => {
+            set_params(params);
+            (board.psqt_mg, board.psqt_eg) = material_and_pst(board);
+            println!("info string loaded eval weights from {path}");
+        }
+        Err(error) => println!("info string could not load eval weights: {error}"),
+    }
+}
+
+// UCI scores are from the side to move's point of view. Mates are reported in moves, counted from
+// the length of the principal variation.
+fn uci_score(eval: f32, turn: bool, pv_length: usize) -> String {
+    let relative = if turn { eval } else { -eval };
+    if relative.is_infinite() {
+        let moves = pv_length.div_ceil(2) as i32;
+        if relative > 0.0 {
+            format!("mate {moves}")
+        } else {
+            format!("mate -{moves}")
+        }
+    } else {
+        format!("cp {}", (relative * 100.0).round() as i32)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::movegen::*;
+use crate::utils::*;
+
+// Proves or disproves a forced mate for the side to move. Returns the mating line against the
+// most stubborn defence for the shortest mate within the given number of moves, or None if
+// there is no forced mate that quick.
+pub fn mate_search(board: &Board, moves: u8) -> Option<Vec<Move>> {
+    // looking for shorter mates first means the first proof found is the fastest one
+    for n in 1..=moves {
+        if let Some(line) = attack(board, n) {
+            return Some(line);
+        }
+    }
+    None
+}
+
+// The attacking side is to move with moves_left moves to deliver mate
+fn attack(board: &Board, moves_left: u8) -> Option<Vec<Move>> {
+    if moves_left == 0 {
+        return None;
+    }
+
+    // checks first, then captures
+    let mut children: Vec<(Move, Board, bool)> = Vec::new();
+    for ply in legal_moves(board) {
+        let after = make_move(board, &ply);
+        let check = in_check(&after, after.turn);
+        // the mating move has to give check
+        if moves_left == 1 && !check {
+            continue;
+        }
+        children.push((ply, after, check));
+    }
+    children.sort_by_key(|(ply, _, check)| (!check, ply.kind & CAPTURE == 0));
+
+    for (ply, after, _) in children {
+        if let Some(line) = defend(&after, moves_left) {
+            let mut result = vec![ply];
+            result.extend(line);
+            return Some(result);
+        }
+    }
+    None
+}
+
+// The defending side is to move. Returns the line where every reply gets mated, following the
+// reply that holds out the longest, or None if some reply escapes.
+fn defend(board: &Board, moves_left: u8) -> Option<Vec<Move>> {
+    let mut replies = legal_moves(board);
+    if replies.is_empty() {
+        if in_check(board, board.turn) {
+            return Some(Vec::new());
+        }
+        // stalemate
+        return None;
+    }
+    if moves_left == 1 {
+        return None;
+    }
+
+    // captures first since taking an attacker is the likeliest escape
+    replies.sort_by_key(|ply| ply.kind & CAPTURE == 0);
+
+    let mut longest: Option<Vec<Move>> = None;
+    for reply in replies {
+        let after = make_move(board, &reply);
+        let line = attack(&after, moves_left - 1)?;
+        if longest.as_ref().is_none_or(|l| line.len() + 1 > l.len()) {
+            let mut result = vec![reply];
+            result.extend(line);
+            longest = Some(result);
+        }
+    }
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_in_one() {
+        let board = fen_to_board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let line = mate_search(&board, 3).unwrap();
+        assert_eq!(line.len(), 1);
+        assert_eq!(move_to_uci(&line[0]), "a1a8");
+    }
+
+    #[test]
+    fn mate_in_two() {
+        let board = fen_to_board("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        assert_eq!(mate_search(&board, 1), None);
+
+        let line = mate_search(&board, 2).unwrap();
+        assert_eq!(line.len(), 3);
+        let mut position = board;
+        for ply in line.iter() {
+            position = make_move(&position, ply);
+        }
+        assert!(legal_moves(&position).is_empty());
+        assert!(in_check(&position, position.turn));
+    }
+
+    #[test]
+    fn stalemate_is_not_mate() {
+        let board = fen_to_board("8/8/8/8/8/1q6/2k5/K7 w - - 0 1");
+        assert_eq!(defend(&board, 2), None);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::eval::{EvalSink, Score};
+use crate::hash::material_key;
+use crate::params::*;
+use crate::utils::*;
+use std::cell::RefCell;
+
+// Number of material configurations remembered. Must be a power of two
+const MATERIAL_TABLE_SIZE: usize = 1 << 12;
+
+#[derive(Clone, Default)]
+struct MaterialEntry {
+    key: u64,
+    // white's then black's, each from its own side's point of view as (middlegame, endgame)
+    scores: [(i32, i32); 2],
+}
+
+thread_local! {
+    static MATERIAL_TABLE: RefCell<Vec<MaterialEntry>> =
+        RefCell::new(vec![MaterialEntry::default(); MATERIAL_TABLE_SIZE]);
+}
+
+// Forgets every cached imbalance on this thread, for when the weights change
+pub fn clear_material_table() {
+    MATERIAL_TABLE.with(|table| table.borrow_mut().fill(MaterialEntry::default()));
+}
+
+// Each side's imbalance score from its own point of view as (middlegame, endgame), White's first.
+// It depends on nothing but the piece counts, so it is worked out once per configuration.
+pub fn material_scores(board: &Board, params: &EvalParams) -> [(i32, i32); 2] {
+    let key = material_key(board);
+    // configurations close to each other differ in few bits of the key, so mix it up first
+    let index =
+        (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - MATERIAL_TABLE_SIZE.ilog2())) as usize;
+
+    MATERIAL_TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        if table[index].key != key {
+            let mut score = Score::new(params);
+            imbalance_features(board, &mut score);
+            table[index] = MaterialEntry {
+                key,
+                scores: score.sides,
+            };
+        }
+        table[index].scores
+    })
+}
+
+// Counts of the bishop pair, pawns, knights, bishops, rooks and queens for each side, White's
+// first
+fn piece_counts(board: &Board) -> [[i32; 6]; 2] {
+    let side = |pawns: u64, knights: u64, bishops: u64, rooks: u64, queens: u64| {
+        [
+            (bishops.count_ones() >= 2) as i32,
+            pawns.count_ones() as i32,
+            knights.count_ones() as i32,
+            bishops.count_ones() as i32,
+            rooks.count_ones() as i32,
+            queens.count_ones() as i32,
+        ]
+    };
+    [
+        side(
+            board.white_pawn,
+            board.white_knight,
+            board.white_bishop,
+            board.white_rook,
+            board.white_queen,
+        ),
+        side(
+            board.black_pawn,
+            board.black_knight,
+            board.black_bishop,
+            board.black_rook,
+            board.black_queen,
+        ),
+    ]
+}
+
+// Every pair of piece kinds, scored by the product of their counts, skipping the cache
+pub fn imbalance_features(board: &Board, sink: &mut impl EvalSink) {
+    let counts = piece_counts(board);
+    for (side, white) in [(0, true), (1, false)] {
+        let (ours, theirs) = (counts[side], counts[1 - side]);
+        for a in 0..6 {
+            if ours[a] == 0 {
+                continue;
+            }
+            for b in 0..=a {
+                let pair = a * (a + 1) / 2 + b;
+                if ours[b] != 0 {
+                    sink.add(IMBALANCE_OURS + pair, white, ours[a] * ours[b]);
+                }
+                if theirs[b] != 0 {
+                    sink.add(IMBALANCE_THEIRS + pair, white, ours[a] * theirs[b]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // White's imbalance score from scratch, middlegame and endgame being the same
+    fn white_score(fen: &str) -> i32 {
+        let params = EvalParams::default();
+        let mut score = Score::new(&params);
+        imbalance_features(&fen_to_board(fen), &mut score);
+        score.sides[0].0 - score.sides[1].0
+    }
+
+    #[test]
+    fn cache_matches_features() {
+        let params = EvalParams::default();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "2r3k1/1q3ppp/p3p3/1p1nP3/3P4/P2B1Q2/1P3PPP/2R3K1 b - - 0 25",
+        ] {
+            let board = fen_to_board(fen);
+            let mut score = Score::new(&params);
+            imbalance_features(&board, &mut score);
+            // the second lookup comes from the table
+            assert_eq!(material_scores(&board, &params), score.sides);
+            assert_eq!(material_scores(&board, &params), score.sides);
+        }
+    }
+
+    #[test]
+    fn rooks_and_minors() {
+        // two minor pieces do better against a rook than their values alone say
+        assert!(white_score("4k3/3r4/8/8/8/8/8/2N1KB2 w - - 0 1") > 0);
+        // and a queen against two rooks
+        assert!(white_score("3rkr2/8/8/8/8/8/8/3QK3 w - - 0 1") > 0);
+    }
+}
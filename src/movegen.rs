@@ -1,4 +1,6 @@
 use crate::bitboard::MAGIC_TABLES;
+use crate::eval::piece_score;
+use crate::params::with_params;
 use crate::utils::*;
 use std::thread;
 use std::time::Instant;
@@ -27,10 +29,21 @@ use std::time::Instant;
 
 // Takes in a board  and a move and returns an updated board with the move made
 pub fn make_move(before: &Board, ply: &Move) -> Board {
+    let mut after = move_pieces(before, ply);
+    update_scores(before, &mut after, ply);
+    after
+}
+
+// Moves the pieces and updates the flags, leaving the scores Board keeps incrementally as they
+// were. Enough to see whether a move leaves the king in check, which is all legal_moves wants.
+fn move_pieces(before: &Board, ply: &Move) -> Board {
     let mut after: Board = before.clone();
     let from_mask = !(1 << ply.from);
     let to_mask = 1 << ply.to;
 
+    // pawn moves and captures reset this back to 0 below
+    after.halfmove += 1;
+
     // a white move
     if before.turn {
         after.white &= from_mask;
@@ -513,11 +526,81 @@ pub fn make_move(before: &Board, ply: &Move) -> Board {
         after.ep_target = None;
     }
 
-    after.halfmove += 1;
     after.turn = !before.turn;
     after
 }
 
+// Applies the move's change in material and piece-square scores to the board after it
+fn update_scores(before: &Board, after: &mut Board, ply: &Move) {
+    with_params(|params| {
+        for (piece, square, colour, sign) in piece_changes(before, ply).into_iter().flatten() {
+            let (mg, eg) = piece_score(params, piece, square, colour);
+            after.psqt_mg += sign * mg;
+            after.psqt_eg += sign * eg;
+        }
+    });
+}
+
+// The pieces a move takes off and puts on the board, as (piece index, square, white, sign) with
+// a sign of -1 for a removed piece and 1 for a placed one. Anything kept incrementally, like the
+// piece-square scores or the network accumulators, is updated from this list. No move changes
+// more than four, so the rest of the entries are None.
+pub fn piece_changes(before: &Board, ply: &Move) -> [Option<(usize, u8, bool, i32)>; 4] {
+    let white = before.turn;
+    let mut changes = [None; 4];
+
+    // piece codes have one bit per piece type above the two colour bits
+    let moved = (ply.piece >> 2).trailing_zeros() as usize;
+    let placed = if ply.kind & KNIGHT_PROMO != 0 {
+        1 + (ply.kind & 0b11) as usize
+    } else {
+        moved
+    };
+    changes[0] = Some((moved, ply.from, white, -1));
+    changes[1] = Some((placed, ply.to, white, 1));
+
+    if ply.kind == EN_PASSANT {
+        let square = if white { ply.to - 8 } else { ply.to + 8 };
+        changes[2] = Some((0, square, !white, -1));
+    } else if ply.kind & CAPTURE != 0 {
+        // kings are never captured
+        let enemy = if white {
+            [
+                before.black_pawn,
+                before.black_knight,
+                before.black_bishop,
+                before.black_rook,
+                before.black_queen,
+            ]
+        } else {
+            [
+                before.white_pawn,
+                before.white_knight,
+                before.white_bishop,
+                before.white_rook,
+                before.white_queen,
+            ]
+        };
+        if let Some(captured) = enemy.iter().position(|b| b & (1 << ply.to) != 0) {
+            changes[2] = Some((captured, ply.to, !white, -1));
+        }
+    }
+
+    let rook_move = match (ply.kind, white) {
+        (KINGSIDE_CASTLE, true) => Some((7, 5)),
+        (QUEENSIDE_CASTLE, true) => Some((0, 3)),
+        (KINGSIDE_CASTLE, false) => Some((63, 61)),
+        (QUEENSIDE_CASTLE, false) => Some((56, 59)),
+        _ => None,
+    };
+    if let Some((from, to)) = rook_move {
+        changes[2] = Some((3, from, white, -1));
+        changes[3] = Some((3, to, white, 1));
+    }
+
+    changes
+}
+
 // Takes in a board state and returns a Vec of all legal moves
 pub fn legal_moves(board: &Board) -> Vec<Move> {
     let mut moves: Vec<Move> = Vec::new();
@@ -627,7 +710,7 @@ fn pawn_moves(board: &Board) -> Vec<Move> {
                 to,
                 kind,
             };
-            let new_board = make_move(&board, &ply);
+            let new_board = move_pieces(&board, &ply);
             if !in_check(&new_board, board.turn) {
                 moves.push(ply);
             }
@@ -640,7 +723,7 @@ fn pawn_moves(board: &Board) -> Vec<Move> {
             to,
             kind,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -789,7 +872,7 @@ fn knight_moves(board: &Board, position: u8) -> Vec<Move> {
             to: dest,
             kind: QUIET_MOVE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -801,7 +884,7 @@ fn knight_moves(board: &Board, position: u8) -> Vec<Move> {
             to: dest,
             kind: CAPTURE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -850,7 +933,7 @@ fn bishop_moves(board: &Board, position: u8, attacks: &Vec<u64>, magic: u64) ->
             to: dest,
             kind: QUIET_MOVE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -862,7 +945,7 @@ fn bishop_moves(board: &Board, position: u8, attacks: &Vec<u64>, magic: u64) ->
             to: dest,
             kind: CAPTURE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -912,7 +995,7 @@ fn rook_moves(board: &Board, position: u8, attacks: &Vec<u64>, magic: u64) -> Ve
             to: dest,
             kind: QUIET_MOVE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -924,7 +1007,7 @@ fn rook_moves(board: &Board, position: u8, attacks: &Vec<u64>, magic: u64) -> Ve
             to: dest,
             kind: CAPTURE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -978,7 +1061,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
             to: dest,
             kind: QUIET_MOVE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -990,7 +1073,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
             to: dest,
             kind: CAPTURE,
         };
-        let new_board = make_move(&board, &ply);
+        let new_board = move_pieces(&board, &ply);
         if !in_check(&new_board, board.turn) {
             moves.push(ply);
         }
@@ -1009,7 +1092,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                         to: 5,
                         kind: QUIET_MOVE,
                     };
-                    let check_test_board = make_move(&board, &check_test);
+                    let check_test_board = move_pieces(&board, &check_test);
                     if !in_check(&check_test_board, board.turn) {
                         let ply = Move {
                             piece,
@@ -1017,7 +1100,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                             to: 6,
                             kind: KINGSIDE_CASTLE,
                         };
-                        let new_board = make_move(&board, &ply);
+                        let new_board = move_pieces(&board, &ply);
                         if !in_check(&new_board, board.turn) {
                             moves.push(ply);
                         }
@@ -1034,7 +1117,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                         to: 3,
                         kind: QUIET_MOVE,
                     };
-                    let check_test_board = make_move(&board, &check_test);
+                    let check_test_board = move_pieces(&board, &check_test);
                     if !in_check(&check_test_board, board.turn) {
                         let ply = Move {
                             piece,
@@ -1042,7 +1125,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                             to: 2,
                             kind: QUEENSIDE_CASTLE,
                         };
-                        let new_board = make_move(&board, &ply);
+                        let new_board = move_pieces(&board, &ply);
                         if !in_check(&new_board, board.turn) {
                             moves.push(ply);
                         }
@@ -1061,7 +1144,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                         to: 61,
                         kind: QUIET_MOVE,
                     };
-                    let check_test_board = make_move(&board, &check_test);
+                    let check_test_board = move_pieces(&board, &check_test);
                     if !in_check(&check_test_board, board.turn) {
                         let ply = Move {
                             piece,
@@ -1069,7 +1152,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                             to: 62,
                             kind: KINGSIDE_CASTLE,
                         };
-                        let new_board = make_move(&board, &ply);
+                        let new_board = move_pieces(&board, &ply);
                         if !in_check(&new_board, board.turn) {
                             moves.push(ply);
                         }
@@ -1086,7 +1169,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                         to: 59,
                         kind: QUIET_MOVE,
                     };
-                    let check_test_board = make_move(&board, &check_test);
+                    let check_test_board = move_pieces(&board, &check_test);
                     if !in_check(&check_test_board, board.turn) {
                         let ply = Move {
                             piece,
@@ -1094,7 +1177,7 @@ fn king_moves(board: &Board, position: u8) -> Vec<Move> {
                             to: 58,
                             kind: QUEENSIDE_CASTLE,
                         };
-                        let new_board = make_move(&board, &ply);
+                        let new_board = move_pieces(&board, &ply);
                         if !in_check(&new_board, board.turn) {
                             moves.push(ply);
                         }
@@ -1250,6 +1333,8 @@ fn create_test_board() -> Board {
         ep_target: None,
         halfmove: 0,
         fullmove: 0,
+        psqt_mg: 0,
+        psqt_eg: 0,
     }
 }
 
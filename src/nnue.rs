@@ -0,0 +1,335 @@
+use crate::eval::piece_boards;
+use crate::movegen::piece_changes;
+use crate::utils::*;
+use std::fmt;
+use std::fs;
+use std::io;
+
+// A 768 -> N x 2 -> 1 network. Each side has its own accumulator of N values, built from the 768
+// piece/square inputs seen from that side (own pieces first, squares flipped for Black). The side
+// to move's accumulator and the other one go through a clipped ReLU and into a single output.
+//
+// File layout, all little endian:
+//   "IKNN", u32 version, u32 hidden size N
+//   768 * N i16 feature weights, one row of N per input
+//   N i16 feature biases
+//   2 * N i16 output weights, side to move's half first
+//   i32 output bias
+const MAGIC: &[u8; 4] = b"IKNN";
+const VERSION: u32 = 1;
+const INPUTS: usize = 768;
+
+// Quantisation: accumulator values are scaled by QA, output weights by QB
+const QA: i32 = 255;
+const QB: i32 = 64;
+// Centipawns per unit of network output
+const SCALE: i32 = 400;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+// The hidden layer before activation, from White's side and from Black's
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Network({INPUTS} -> {} x 2 -> 1)", self.hidden)
+    }
+}
+
+// Input index of a piece seen from one side
+fn feature(piece: usize, square: u8, white: bool, perspective: bool) -> usize {
+    let side = if white == perspective { 0 } else { 384 };
+    let square = if perspective { square } else { square ^ 56 };
+    side + piece * 64 + square as usize
+}
+
+impl Network {
+    pub fn load(path: &str) -> io::Result<Network> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Network> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        if bytes.len() < 12 || &bytes[0..4] != MAGIC {
+            return Err(invalid("not a network file"));
+        }
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        if word(4) != VERSION {
+            return Err(invalid("unsupported network version"));
+        }
+        let hidden = word(8) as usize;
+        if hidden == 0 || bytes.len() != 12 + 2 * (INPUTS + 3) * hidden + 4 {
+            return Err(invalid("network file has the wrong size"));
+        }
+
+        let mut at = 12;
+        let mut read = |count: usize| -> Vec<i16> {
+            let values = bytes[at..at + 2 * count]
+                .chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            at += 2 * count;
+            values
+        };
+        let feature_weights = read(INPUTS * hidden);
+        let feature_biases = read(hidden);
+        let output_weights = read(2 * hidden);
+        let output_bias = word(bytes.len() - 4) as i32;
+
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.hidden as u32).to_le_bytes());
+        for values in [
+            &self.feature_weights,
+            &self.feature_biases,
+            &self.output_weights,
+        ] {
+            for value in values.iter() {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes.extend(self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    fn row(&self, index: usize) -> &[i16] {
+        &self.feature_weights[index * self.hidden..(index + 1) * self.hidden]
+    }
+
+    // Builds both accumulators from scratch
+    pub fn refresh(&self, board: &Board) -> Accumulator {
+        let mut accumulator = Accumulator {
+            values: [self.feature_biases.clone(), self.feature_biases.clone()],
+        };
+        let (white_pieces, black_pieces) = piece_boards(board);
+        for (white, pieces) in [(true, white_pieces), (false, black_pieces)] {
+            for (piece, &bitboard) in pieces.iter().enumerate() {
+                let mut remaining = bitboard;
+                while remaining != 0 {
+                    let square = remaining.trailing_zeros() as u8;
+                    remaining &= remaining - 1;
+                    self.apply(&mut accumulator, piece, square, white, 1);
+                }
+            }
+        }
+        accumulator
+    }
+
+    fn apply(
+        &self,
+        accumulator: &mut Accumulator,
+        piece: usize,
+        square: u8,
+        white: bool,
+        sign: i32,
+    ) {
+        for (side, perspective) in [(0, true), (1, false)] {
+            let row = self.row(feature(piece, square, white, perspective));
+            let values = &mut accumulator.values[side];
+            // a network from a file may have weights that sum past i16. Wrapping keeps an
+            // updated accumulator equal to a refreshed one either way, and is what the AVX2
+            // registers do
+            if sign > 0 {
+                values
+                    .iter_mut()
+                    .zip(row)
+                    .for_each(|(v, &w)| *v = v.wrapping_add(w));
+            } else {
+                values
+                    .iter_mut()
+                    .zip(row)
+                    .for_each(|(v, &w)| *v = v.wrapping_sub(w));
+            }
+        }
+    }
+
+    // Sets accumulators[height + 1] to the accumulator after playing ply from the position at
+    // height, changing only the inputs the move touches
+    pub fn update(
+        &self,
+        accumulators: &mut Vec<Accumulator>,
+        height: usize,
+        before: &Board,
+        ply: &Move,
+    ) {
+        if accumulators.len() <= height + 1 {
+            accumulators.resize(height + 2, accumulators[height].clone());
+        }
+        let (above, below) = accumulators.split_at_mut(height + 1);
+        let child = &mut below[0];
+        for side in 0..2 {
+            child.values[side].copy_from_slice(&above[height].values[side]);
+        }
+        for (piece, square, white, sign) in piece_changes(before, ply).into_iter().flatten() {
+            self.apply(child, piece, square, white, sign);
+        }
+    }
+
+    // Centipawns from White's point of view, matching the handcrafted eval
+    pub fn evaluate(&self, accumulator: &Accumulator, turn: bool) -> i32 {
+        let (us, them) = if turn { (0, 1) } else { (1, 0) };
+        let (us_weights, them_weights) = self.output_weights.split_at(self.hidden);
+        let sum = crelu_dot(&accumulator.values[us], us_weights)
+            + crelu_dot(&accumulator.values[them], them_weights);
+        let score = (sum + self.output_bias) * SCALE / (QA * QB);
+        if turn {
+            score
+        } else {
+            -score
+        }
+    }
+}
+
+// Dot product of the clipped accumulator with the output weights, using AVX2 when the CPU has
+// it and the layer is a whole number of 16 lane registers wide
+fn crelu_dot(values: &[i16], weights: &[i16]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if values.len().is_multiple_of(16) && is_x86_feature_detected!("avx2") {
+        return unsafe { crelu_dot_avx2(values, weights) };
+    }
+    crelu_dot_scalar(values, weights)
+}
+
+// Wraps on overflow like the 32 bit AVX2 lanes
+fn crelu_dot_scalar(values: &[i16], weights: &[i16]) -> i32 {
+    values
+        .iter()
+        .zip(weights)
+        .map(|(&v, &w)| (v as i32).clamp(0, QA) * w as i32)
+        .fold(0, i32::wrapping_add)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn crelu_dot_avx2(values: &[i16], weights: &[i16]) -> i32 {
+    use std::arch::x86_64::*;
+
+    let zero = _mm256_setzero_si256();
+    let ceiling = _mm256_set1_epi16(QA as i16);
+    let mut sum = _mm256_setzero_si256();
+    for i in (0..values.len()).step_by(16) {
+        let v = _mm256_loadu_si256(values.as_ptr().add(i) as *const __m256i);
+        let w = _mm256_loadu_si256(weights.as_ptr().add(i) as *const __m256i);
+        let clipped = _mm256_min_epi16(_mm256_max_epi16(v, zero), ceiling);
+        // clipped values fit in 8 bits, so each pair of products fits in 32
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, w));
+    }
+
+    let halves = _mm_add_epi32(
+        _mm256_castsi256_si128(sum),
+        _mm256_extracti128_si256(sum, 1),
+    );
+    let pairs = _mm_add_epi32(halves, _mm_shuffle_epi32(halves, 0b01_00_11_10));
+    let total = _mm_add_epi32(pairs, _mm_shuffle_epi32(pairs, 0b10_11_00_01));
+    _mm_cvtsi128_si32(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::*;
+
+    // Small deterministic network so the tests don't need a file on disk
+    fn test_network(hidden: usize) -> Network {
+        let mut state: u32 = 12345;
+        let mut next = |range: i32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) as i32 % (2 * range + 1) - range) as i16
+        };
+        Network {
+            hidden,
+            feature_weights: (0..INPUTS * hidden).map(|_| next(40)).collect(),
+            feature_biases: (0..hidden).map(|_| next(60)).collect(),
+            output_weights: (0..2 * hidden).map(|_| next(100)).collect(),
+            output_bias: 1000,
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let network = test_network(16);
+        let loaded = Network::from_bytes(&network.to_bytes()).unwrap();
+        assert_eq!(loaded.to_bytes(), network.to_bytes());
+        assert!(Network::from_bytes(&network.to_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn incremental_matches_refresh() {
+        let network = test_network(32);
+        // castling, en passant and promotions all come up within two plies of these
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ] {
+            let board = fen_to_board(fen);
+            let mut accumulators = vec![network.refresh(&board)];
+            for ply in legal_moves(&board) {
+                let child = make_move(&board, &ply);
+                network.update(&mut accumulators, 0, &board, &ply);
+                assert_eq!(accumulators[1], network.refresh(&child));
+                for reply in legal_moves(&child) {
+                    network.update(&mut accumulators, 1, &child, &reply);
+                    assert_eq!(accumulators[2], network.refresh(&make_move(&child, &reply)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_weights_wrap() {
+        // every feature pushes the accumulator as far as one weight can, so two pieces are
+        // enough to pass the i16 range. None of it may panic
+        let mut network = test_network(16);
+        network.feature_weights.fill(i16::MAX);
+        let board = starting_position();
+        let mut accumulators = vec![network.refresh(&board)];
+        for ply in legal_moves(&board) {
+            network.update(&mut accumulators, 0, &board, &ply);
+            assert_eq!(accumulators[1], network.refresh(&make_move(&board, &ply)));
+        }
+        network.evaluate(&accumulators[0], true);
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let network = test_network(64);
+        let board = starting_position();
+        let accumulator = network.refresh(&board);
+        let weights = &network.output_weights[..64];
+        assert_eq!(
+            crelu_dot(&accumulator.values[0], weights),
+            crelu_dot_scalar(&accumulator.values[0], weights)
+        );
+    }
+
+    #[test]
+    fn symmetric_start() {
+        // the start position looks the same from both sides, so the side to move decides the sign
+        let network = test_network(16);
+        let accumulator = network.refresh(&starting_position());
+        assert_eq!(accumulator.values[0], accumulator.values[1]);
+        assert_eq!(
+            network.evaluate(&accumulator, true),
+            -network.evaluate(&accumulator, false)
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::eval::clear_eval_cache;
+use crate::material::clear_material_table;
+use crate::pawns::clear_pawn_table;
+use crate::pst::*;
+use lazy_static::lazy_static;
+use std::cell::RefCell;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+// Every evaluation weight lives in one flat table of (middlegame, endgame) pairs in centipawns,
+// so the tuner can treat them all alike. Each constant below is the index of the first weight of
+// a group; the eval adds weights by index rather than naming values directly.
+pub const PIECE_VALUE: usize = 0;
+// 64 squares per piece from White's point of view, pawns first. Black looks up square ^ 56
+pub const PST: usize = PIECE_VALUE + 6;
+
+// Pawn structure. CONNECTED and the passed pawn groups are indexed by rank counted from the
+// pawn's own side
+pub const DOUBLED: usize = PST + 6 * 64;
+pub const ISOLATED: usize = DOUBLED + 1;
+pub const BACKWARD: usize = ISOLATED + 1;
+pub const CONNECTED: usize = BACKWARD + 1;
+pub const PASSED: usize = CONNECTED + 8;
+// a passed pawn that can't move right now
+pub const PASSED_BLOCKED: usize = PASSED + 8;
+
+// King safety. Shield and storm are indexed by how many ranks in front of the king the nearest
+// pawn on a file is, and KING_DANGER by attack units on the king zone, capped at the last entry
+pub const SHIELD: usize = PASSED_BLOCKED + 8;
+pub const STORM: usize = SHIELD + 3;
+pub const OPEN_FILE_NEAR_KING: usize = STORM + 5;
+pub const SEMI_OPEN_FILE_NEAR_KING: usize = OPEN_FILE_NEAR_KING + 1;
+pub const KING_DANGER: usize = SEMI_OPEN_FILE_NEAR_KING + 1;
+pub const KING_DANGER_SIZE: usize = 33;
+
+// Pieces. MOBILITY is per safe square above or below a typical count, for knights, bishops,
+// rooks and queens
+pub const MOBILITY: usize = KING_DANGER + KING_DANGER_SIZE;
+pub const ROOK_OPEN_FILE: usize = MOBILITY + 4;
+pub const ROOK_SEMI_OPEN_FILE: usize = ROOK_OPEN_FILE + 1;
+pub const ROOK_ON_SEVENTH: usize = ROOK_SEMI_OPEN_FILE + 1;
+pub const BISHOP_PAIR: usize = ROOK_ON_SEVENTH + 1;
+pub const KNIGHT_OUTPOST: usize = BISHOP_PAIR + 1;
+pub const TRAPPED_BISHOP: usize = KNIGHT_OUTPOST + 1;
+pub const TRAPPED_ROOK: usize = TRAPPED_BISHOP + 1;
+
+// Threats. THREAT_BY_LESSER is indexed by the attacked piece, knights to queens
+pub const THREAT_BY_LESSER: usize = TRAPPED_ROOK + 1;
+pub const HANGING: usize = THREAT_BY_LESSER + 4;
+pub const PAWN_PUSH_THREAT: usize = HANGING + 1;
+
+// Space and centre. SPACE is per safe square behind the front line, counted again when it is
+// behind a pawn. CENTRE_CONTROL is per attack on d4, e4, d5 and e5
+pub const SPACE: usize = PAWN_PUSH_THREAT + 1;
+pub const CENTRE_CONTROL: usize = SPACE + 1;
+
+// Material imbalance, a weight for each pair of piece kinds scored by the product of their
+// counts. Kinds are the bishop pair, pawns, knights, bishops, rooks and queens, and the pair
+// (a, b) with b <= a is at a * (a + 1) / 2 + b. OURS pairs a side's pieces with its own, THEIRS
+// with the other side's
+pub const IMBALANCE_OURS: usize = CENTRE_CONTROL + 1;
+pub const IMBALANCE_THEIRS: usize = IMBALANCE_OURS + 21;
+
+// Bonus for having the move
+pub const TEMPO: usize = IMBALANCE_THEIRS + 21;
+
+pub const PARAM_COUNT: usize = TEMPO + 1;
+
+// The lines of an eval trace, which groups are summed into
+pub const TERMS: [&str; 10] = [
+    "material",
+    "pst",
+    "pawns",
+    "king safety",
+    "mobility",
+    "pieces",
+    "threats",
+    "space",
+    "imbalance",
+    "tempo",
+];
+
+pub struct Group {
+    pub name: &'static str,
+    pub start: usize,
+    pub len: usize,
+    // index into TERMS
+    pub term: usize,
+}
+
+const fn group(name: &'static str, start: usize, len: usize, term: usize) -> Group {
+    Group {
+        name,
+        start,
+        len,
+        term,
+    }
+}
+
+pub const GROUPS: [Group; 29] = [
+    group("PIECE_VALUE", PIECE_VALUE, 6, 0),
+    group("PST", PST, 6 * 64, 1),
+    group("DOUBLED", DOUBLED, 1, 2),
+    group("ISOLATED", ISOLATED, 1, 2),
+    group("BACKWARD", BACKWARD, 1, 2),
+    group("CONNECTED", CONNECTED, 8, 2),
+    group("PASSED", PASSED, 8, 2),
+    group("PASSED_BLOCKED", PASSED_BLOCKED, 8, 2),
+    group("SHIELD", SHIELD, 3, 3),
+    group("STORM", STORM, 5, 3),
+    group("OPEN_FILE_NEAR_KING", OPEN_FILE_NEAR_KING, 1, 3),
+    group("SEMI_OPEN_FILE_NEAR_KING", SEMI_OPEN_FILE_NEAR_KING, 1, 3),
+    group("KING_DANGER", KING_DANGER, KING_DANGER_SIZE, 3),
+    group("MOBILITY", MOBILITY, 4, 4),
+    group("ROOK_OPEN_FILE", ROOK_OPEN_FILE, 1, 5),
+    group("ROOK_SEMI_OPEN_FILE", ROOK_SEMI_OPEN_FILE, 1, 5),
+    group("ROOK_ON_SEVENTH", ROOK_ON_SEVENTH, 1, 5),
+    group("BISHOP_PAIR", BISHOP_PAIR, 1, 5),
+    group("KNIGHT_OUTPOST", KNIGHT_OUTPOST, 1, 5),
+    group("TRAPPED_BISHOP", TRAPPED_BISHOP, 1, 5),
+    group("TRAPPED_ROOK", TRAPPED_ROOK, 1, 5),
+    group("THREAT_BY_LESSER", THREAT_BY_LESSER, 4, 6),
+    group("HANGING", HANGING, 1, 6),
+    group("PAWN_PUSH_THREAT", PAWN_PUSH_THREAT, 1, 6),
+    group("SPACE", SPACE, 1, 7),
+    group("CENTRE_CONTROL", CENTRE_CONTROL, 1, 7),
+    group("IMBALANCE_OURS", IMBALANCE_OURS, 21, 8),
+    group("IMBALANCE_THEIRS", IMBALANCE_THEIRS, 21, 8),
+    group("TEMPO", TEMPO, 1, 9),
+];
+
+// The group a weight belongs to
+pub fn group_of(index: usize) -> &'static Group {
+    GROUPS
+        .iter()
+        .find(|group| (group.start..group.start + group.len).contains(&index))
+        .unwrap()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalParams {
+    pub weights: Vec<(i32, i32)>,
+}
+
+impl Default for EvalParams {
+    // The hand-set weights
+    fn default() -> EvalParams {
+        let mut weights = vec![(0, 0); PARAM_COUNT];
+        let mut set = |start: usize, values: &[(i32, i32)]| {
+            weights[start..start + values.len()].copy_from_slice(values);
+        };
+
+        set(
+            PIECE_VALUE,
+            &[
+                (100, 100),
+                (300, 300),
+                (320, 320),
+                (500, 500),
+                (900, 900),
+                (0, 0),
+            ],
+        );
+
+        set(DOUBLED, &[(-10, -20)]);
+        set(ISOLATED, &[(-10, -15)]);
+        set(BACKWARD, &[(-8, -10)]);
+        #[rustfmt::skip]
+        set(CONNECTED, &[(0, 0), (5, 3), (8, 5), (10, 8), (15, 12), (25, 20), (40, 30), (0, 0)]);
+        #[rustfmt::skip]
+        set(PASSED, &[(0, 0), (5, 10), (10, 15), (15, 25), (30, 45), (50, 75), (80, 120), (0, 0)]);
+        #[rustfmt::skip]
+        set(PASSED_BLOCKED, &[(0, 0), (2, 5), (5, 7), (7, 12), (15, 22), (25, 37), (40, 60), (0, 0)]);
+
+        // king safety only matters in the middlegame to begin with
+        set(SHIELD, &[(0, 0), (15, 0), (8, 0)]);
+        set(STORM, &[(0, 0), (0, 0), (-25, 0), (-15, 0), (-5, 0)]);
+        set(OPEN_FILE_NEAR_KING, &[(-25, 0)]);
+        set(SEMI_OPEN_FILE_NEAR_KING, &[(-12, 0)]);
+
+        set(MOBILITY, &[(4, 4), (5, 5), (2, 4), (1, 2)]);
+        set(ROOK_OPEN_FILE, &[(25, 10)]);
+        set(ROOK_SEMI_OPEN_FILE, &[(12, 6)]);
+        set(ROOK_ON_SEVENTH, &[(20, 30)]);
+        set(BISHOP_PAIR, &[(30, 50)]);
+        set(KNIGHT_OUTPOST, &[(20, 10)]);
+        set(TRAPPED_BISHOP, &[(-100, -100)]);
+        set(TRAPPED_ROOK, &[(-40, -10)]);
+
+        set(THREAT_BY_LESSER, &[(40, 30), (40, 30), (60, 40), (70, 50)]);
+        set(HANGING, &[(30, 20)]);
+        set(PAWN_PUSH_THREAT, &[(20, 15)]);
+
+        // space stops mattering once the pieces come off
+        set(SPACE, &[(3, 0)]);
+        set(CENTRE_CONTROL, &[(5, 0)]);
+
+        // knights want pawns to work with, and rooks and queens get in each other's way while
+        // minor pieces help them out. Bishop pairs on their own are scored by BISHOP_PAIR
+        #[rustfmt::skip]
+        let ours = [
+            0,
+            0, 0,
+            0, 1, -3,
+            0, 0, 0, 0,
+            -2, 0, 2, 2, -8,
+            -5, 0, 2, 2, -6, 0,
+        ];
+        #[rustfmt::skip]
+        let theirs = [
+            0,
+            0, 0,
+            0, 0, 0,
+            2, 0, 0, 0,
+            2, 0, 1, -1, 0,
+            4, 0, 0, 3, 7, 0,
+        ];
+        set(IMBALANCE_OURS, &ours.map(|weight| (weight, weight)));
+        set(IMBALANCE_THEIRS, &theirs.map(|weight| (weight, weight)));
+
+        set(TEMPO, &[(20, 10)]);
+
+        for piece in 0..6 {
+            for square in 0..64 {
+                weights[PST + piece * 64 + square] =
+                    (MG_TABLES[piece][square], EG_TABLES[piece][square]);
+            }
+        }
+        // grows with the square of the attack units, up to 500
+        for units in 0..KING_DANGER_SIZE as i32 {
+            weights[KING_DANGER + units as usize] = (-(units * units / 2).min(500), 0);
+        }
+
+        EvalParams { weights }
+    }
+}
+
+// Parameter files hold one entry per group, NAME = (mg, eg) for a single weight or
+// NAME = [(mg, eg), ...] for a list, which may run over several lines. # starts a comment.
+// Groups left out keep their default weights.
+impl EvalParams {
+    pub fn load(path: &str) -> Result<EvalParams, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{path}: {error}"))?;
+        EvalParams::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<EvalParams, String> {
+        let mut params = EvalParams::default();
+        let mut seen: Vec<&str> = Vec::new();
+        let tokens = tokenize(text)?;
+        let mut tokens = tokens.iter().peekable();
+
+        while let Some((line, token)) = tokens.next() {
+            let error = |message: String| Err(format!("line {line}: {message}"));
+            let name = match token {
+                Token::Name(name) => name,
+                _ => return error("expected a parameter name".to_string()),
+            };
+            let Some(group) = GROUPS.iter().find(|group| group.name == name) else {
+                return error(format!("unknown parameter {name}"));
+            };
+            if seen.contains(&group.name) {
+                return error(format!("{name} is given twice"));
+            }
+            seen.push(group.name);
+            if !matches!(tokens.next(), Some((_, Token::Symbol('=')))) {
+                return error(format!("expected = after {name}"));
+            }
+
+            let mut values = Vec::new();
+            if matches!(tokens.peek(), Some((_, Token::Symbol('[')))) {
+                tokens.next();
+                loop {
+                    match tokens.peek() {
+                        Some((_, Token::Symbol(']'))) => {
+                            tokens.next();
+                            break;
+                        }
+                        Some(_) => values.push(parse_pair(&mut tokens)?),
+                        None => return error(format!("{name} is missing its closing ]")),
+                    }
+                    if matches!(tokens.peek(), Some((_, Token::Symbol(',')))) {
+                        tokens.next();
+                    }
+                }
+            } else {
+                values.push(parse_pair(&mut tokens)?);
+            }
+
+            if values.len() != group.len {
+                return error(format!(
+                    "{name} needs {} weights but has {}",
+                    group.len,
+                    values.len()
+                ));
+            }
+            params.weights[group.start..group.start + group.len].copy_from_slice(&values);
+        }
+
+        Ok(params)
+    }
+
+    // The whole table in the file format, which parse reads back
+    pub fn to_text(&self) -> String {
+        let mut text =
+            String::from("# Ike evaluation weights as (middlegame, endgame) centipawns\n");
+        for group in GROUPS.iter() {
+            text.push('\n');
+            if group.len == 1 {
+                let (mg, eg) = self.weights[group.start];
+                text.push_str(&format!("{} = ({mg}, {eg})\n", group.name));
+                continue;
+            }
+            text.push_str(&format!("{} = [\n", group.name));
+            for (row, label) in self.rows(group) {
+                text.push_str("    ");
+                text.push_str(&row);
+                if let Some(label) = label {
+                    text.push_str(&format!(" # {label}"));
+                }
+                text.push('\n');
+            }
+            text.push_str("]\n");
+        }
+        text
+    }
+
+    // A group's weights eight to a line, with the piece and rank for the piece-square tables
+    pub fn rows(&self, group: &Group) -> Vec<(String, Option<String>)> {
+        const PIECES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+        let weights = &self.weights[group.start..group.start + group.len];
+        weights
+            .chunks(8)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let values: Vec<String> = chunk
+                    .iter()
+                    .map(|(mg, eg)| format!("({mg:4}, {eg:4}),"))
+                    .collect();
+                let label = if group.start == PST {
+                    Some(format!("{} rank {}", PIECES[row / 8], row % 8 + 1))
+                } else {
+                    None
+                };
+                (values.join(" "), label)
+            })
+            .collect()
+    }
+}
+
+enum Token {
+    Name(String),
+    Number(i32),
+    Symbol(char),
+}
+
+// Splits a parameter file into tokens, each with its line number
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.split('#').next().unwrap();
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if "=[](),".contains(c) {
+                tokens.push((line_number, Token::Symbol(c)));
+                chars.next();
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut name = String::new();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+                {
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push((line_number, Token::Name(name)));
+            } else if c.is_ascii_digit() || c == '-' {
+                let mut number = String::from(c);
+                chars.next();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    number.push(c);
+                    chars.next();
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("line {line_number}: bad number {number}"))?;
+                tokens.push((line_number, Token::Number(value)));
+            } else {
+                return Err(format!("line {line_number}: unexpected {c}"));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Reads (mg, eg)
+fn parse_pair<'a>(
+    tokens: &mut impl Iterator<Item = &'a (usize, Token)>,
+) -> Result<(i32, i32), String> {
+    let mut next = || tokens.next();
+    match (next(), next(), next(), next(), next()) {
+        (
+            Some((_, Token::Symbol('('))),
+            Some((_, Token::Number(mg))),
+            Some((_, Token::Symbol(','))),
+            Some((_, Token::Number(eg))),
+            Some((_, Token::Symbol(')'))),
+        ) => Ok((*mg, *eg)),
+        (Some((line, _)), ..) => Err(format!("line {line}: expected (middlegame, endgame)")),
+        _ => Err("the file ends in the middle of a weight".to_string()),
+    }
+}
+
+lazy_static! {
+    // The weights the engine evaluates with. Only set_params takes the lock to write
+    static ref PARAMS: RwLock<Arc<EvalParams>> = RwLock::new(Arc::new(EvalParams::default()));
+}
+
+// Counts calls to set_params, so a thread can tell its copy of the weights has gone stale
+static PARAMS_VERSION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // This thread's copy of the weights and the PARAMS_VERSION it was taken at
+    static PARAMS_SNAPSHOT: RefCell<Option<(usize, Arc<EvalParams>)>> = const { RefCell::new(None) };
+}
+
+// The weights in use right now. A search or a game takes them once and passes them down, so the
+// lock is never read on the way through the tree.
+pub fn params() -> Arc<EvalParams> {
+    PARAMS.read().unwrap().clone()
+}
+
+// Runs f on this thread's copy of the weights, taken again only after set_params. For code like
+// make_move and fen_to_board that keeps scores in a Board and has no caller to pass weights in.
+pub fn with_params<T>(f: impl FnOnce(&EvalParams) -> T) -> T {
+    PARAMS_SNAPSHOT.with(|snapshot| {
+        let version = PARAMS_VERSION.load(Ordering::Acquire);
+        let mut snapshot = snapshot.borrow_mut();
+        match &*snapshot {
+            Some((taken, _)) if *taken == version => {}
+            _ => *snapshot = Some((version, params())),
+        }
+        f(&snapshot.as_ref().unwrap().1)
+    })
+}
+
+// Switches the engine to new weights. Scores kept incrementally in a Board are worked out with
+// the old weights, so boards made before this need their scores recounted.
+pub fn set_params(params: EvalParams) {
+    *PARAMS.write().unwrap() = Arc::new(params);
+    PARAMS_VERSION.fetch_add(1, Ordering::Release);
+    clear_pawn_table();
+    clear_material_table();
+    clear_eval_cache();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let mut params = EvalParams::default();
+        params.weights[DOUBLED] = (-11, -22);
+        params.weights[PST + 70] = (5, -5);
+        assert_eq!(EvalParams::parse(&params.to_text()), Ok(params));
+    }
+
+    #[test]
+    fn partial_file() {
+        let params = EvalParams::parse(
+            "# just these\nBISHOP_PAIR = (40, 60)\nMOBILITY = [\n(1, 2), (3, 4),\n(5, 6), (7, 8)]",
+        )
+        .unwrap();
+        assert_eq!(params.weights[BISHOP_PAIR], (40, 60));
+        assert_eq!(params.weights[MOBILITY + 3], (7, 8));
+        assert_eq!(
+            params.weights[DOUBLED],
+            EvalParams::default().weights[DOUBLED]
+        );
+    }
+
+    #[test]
+    fn bad_files() {
+        for text in [
+            "NOT_A_WEIGHT = (1, 2)",
+            "DOUBLED = (1, 2)\nDOUBLED = (1, 2)",
+            "MOBILITY = [(1, 2), (3, 4)]",
+            "DOUBLED = (1 2)",
+            "DOUBLED (1, 2)",
+            "SHIELD = [(1, 2), (3, 4), (5, 6)",
+        ] {
+            assert!(EvalParams::parse(text).is_err(), "{text}");
+        }
+        assert_eq!(
+            EvalParams::parse("\nDOUBLED = (1, x)"),
+            Err("line 2: expected (middlegame, endgame)".to_string())
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::eval::{EvalSink, Score};
+use crate::hash::pawn_hash;
+use crate::params::*;
+use crate::utils::*;
+use std::cell::RefCell;
+
+pub const FILES: [u64; 8] = [
+    A_FILE, B_FILE, C_FILE, D_FILE, E_FILE, F_FILE, G_FILE, H_FILE,
+];
+pub const RANKS: [u64; 8] = [
+    FIRST_RANK,
+    SECOND_RANK,
+    THIRD_RANK,
+    FOURTH_RANK,
+    FIFTH_RANK,
+    SIXTH_RANK,
+    SEVENTH_RANK,
+    EIGHTH_RANK,
+];
+
+// Number of pawn structures remembered. Must be a power of two
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+// What is known about one pawn structure. Passed pawns are kept rather than scored since whether
+// they are blocked depends on the pieces
+#[derive(Clone, Default)]
+struct PawnEntry {
+    key: u64,
+    // white's then black's, each from its own side's point of view as (middlegame, endgame)
+    scores: [(i32, i32); 2],
+    passed: [u64; 2],
+}
+
+thread_local! {
+    static PAWN_TABLE: RefCell<Vec<PawnEntry>> =
+        RefCell::new(vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+}
+
+// Forgets every cached structure on this thread, for when the weights change
+pub fn clear_pawn_table() {
+    PAWN_TABLE.with(|table| table.borrow_mut().fill(PawnEntry::default()));
+}
+
+// Squares attacked by the given pawns
+pub fn pawn_attacks(pawns: u64, white: bool) -> u64 {
+    if white {
+        ((pawns & !A_FILE) << 7) | ((pawns & !H_FILE) << 9)
+    } else {
+        ((pawns & !A_FILE) >> 9) | ((pawns & !H_FILE) >> 7)
+    }
+}
+
+pub fn adjacent_files(file: u8) -> u64 {
+    let mut mask = 0;
+    if file > 0 {
+        mask |= FILES[file as usize - 1];
+    }
+    if file < 7 {
+        mask |= FILES[file as usize + 1];
+    }
+    mask
+}
+
+// Every rank in front of the given rank from the point of view of that colour
+pub fn ranks_ahead(rank: u8, white: bool) -> u64 {
+    if white {
+        if rank >= 7 {
+            0
+        } else {
+            u64::MAX << (8 * (rank + 1))
+        }
+    } else {
+        (1u64 << (8 * rank)) - 1
+    }
+}
+
+// Each side's pawn structure score from its own point of view as (middlegame, endgame), White's
+// first
+pub fn pawn_scores(board: &Board, params: &EvalParams) -> [(i32, i32); 2] {
+    let key = pawn_hash(board);
+    let index = key as usize & (PAWN_TABLE_SIZE - 1);
+
+    let entry = PAWN_TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        if table[index].key != key {
+            table[index] = evaluate_pawns(board, key, params);
+        }
+        table[index].clone()
+    });
+
+    let mut score = Score::new(params);
+    score.sides = entry.scores;
+    passed_pawns(board, entry.passed, &mut score);
+    score.sides
+}
+
+// Every pawn feature of the position, skipping the cache
+pub fn pawn_features(board: &Board, sink: &mut impl EvalSink) {
+    let passed = structure(board, sink);
+    passed_pawns(board, passed, sink);
+}
+
+fn evaluate_pawns(board: &Board, key: u64, params: &EvalParams) -> PawnEntry {
+    let mut score = Score::new(params);
+    let passed = structure(board, &mut score);
+    PawnEntry {
+        key,
+        scores: score.sides,
+        passed,
+    }
+}
+
+// The features that only depend on where the pawns are. Returns each side's passed pawns.
+fn structure(board: &Board, sink: &mut impl EvalSink) -> [u64; 2] {
+    let mut passed = [0; 2];
+
+    for (side, white) in [(0, true), (1, false)] {
+        let (own, enemy) = if white {
+            (board.white_pawn, board.black_pawn)
+        } else {
+            (board.black_pawn, board.white_pawn)
+        };
+        let enemy_attacks = pawn_attacks(enemy, !white);
+        let own_attacks = pawn_attacks(own, white);
+
+        for file in FILES {
+            let count = count_ones(own & file) as i32;
+            if count > 1 {
+                sink.add(DOUBLED, white, count - 1);
+            }
+        }
+
+        for square in set_bit_positions(own) {
+            let file = square % 8;
+            let rank = square / 8;
+            let relative_rank = (if white { rank } else { 7 - rank }) as usize;
+            let adjacent = adjacent_files(file);
+            let ahead = ranks_ahead(rank, white);
+
+            if enemy & (FILES[file as usize] | adjacent) & ahead == 0 {
+                passed[side] |= 1 << square;
+            }
+
+            if own & adjacent == 0 {
+                sink.add(ISOLATED, white, 1);
+                continue;
+            }
+
+            let supported = own_attacks & (1 << square) != 0;
+            let phalanx = own & adjacent & RANKS[rank as usize] != 0;
+            if supported || phalanx {
+                sink.add(CONNECTED + relative_rank, white, 1);
+            } else {
+                // no neighbour level with or behind it can come up to defend it, and it can't
+                // safely step forward either
+                let stop = if white { square + 8 } else { square - 8 };
+                let behind = own & adjacent & !ahead;
+                if behind == 0 && enemy_attacks & (1 << stop) != 0 {
+                    sink.add(BACKWARD, white, 1);
+                }
+            }
+        }
+    }
+
+    passed
+}
+
+// Passed pawns are scored outside the cache since whether they are blocked depends on the pieces
+fn passed_pawns(board: &Board, passed: [u64; 2], sink: &mut impl EvalSink) {
+    let occupied = board.white | board.black;
+    for (side, white) in [(0, true), (1, false)] {
+        for square in set_bit_positions(passed[side]) {
+            let rank = (if white { square / 8 } else { 7 - square / 8 }) as usize;
+            let stop = if white { square + 8 } else { square - 8 };
+            if occupied & (1 << stop) != 0 {
+                sink.add(PASSED_BLOCKED + rank, white, 1);
+            } else {
+                sink.add(PASSED + rank, white, 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // White's pawn score from scratch
+    fn white_score(board: &Board) -> (i32, i32) {
+        let params = EvalParams::default();
+        let mut score = Score::new(&params);
+        pawn_features(board, &mut score);
+        score.sides[0]
+    }
+
+    #[test]
+    fn passed_pawns() {
+        let board = fen_to_board("4k3/8/p7/8/3P4/8/1P6/4K3 w - - 0 1");
+        let entry = evaluate_pawns(&board, pawn_hash(&board), &EvalParams::default());
+        assert_eq!(entry.passed[0], 1 << square_to_index("d4"));
+        assert_eq!(entry.passed[1], 0);
+    }
+
+    #[test]
+    fn weaknesses_cost() {
+        let healthy = fen_to_board("4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1");
+        let doubled = fen_to_board("4k3/8/8/8/8/4P3/4P3/4K3 w - - 0 1");
+        let isolated = fen_to_board("4k3/8/8/8/8/8/2P1P3/4K3 w - - 0 1");
+        let (healthy, _) = white_score(&healthy);
+        assert!(white_score(&doubled).0 < healthy);
+        assert!(white_score(&isolated).0 < healthy);
+    }
+
+    #[test]
+    fn backward_pawn() {
+        // d3 can't be defended by c4 and d4 is covered by the e5 pawn
+        let board = fen_to_board("4k3/8/8/4p3/2P5/3P4/8/4K3 w - - 0 1");
+        let without = fen_to_board("4k3/8/8/8/2P5/3P4/8/4K3 w - - 0 1");
+        let params = EvalParams::default();
+        let entry = evaluate_pawns(&board, 0, &params);
+        assert_eq!(
+            entry.scores[0].0,
+            evaluate_pawns(&without, 0, &params).scores[0].0 + params.weights[BACKWARD].0
+        );
+        // the e5 pawn is isolated
+        assert_eq!(entry.scores[1].0, params.weights[ISOLATED].0);
+    }
+
+    #[test]
+    fn blocked_passer_is_worth_less() {
+        let free = fen_to_board("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1");
+        let blocked = fen_to_board("4k3/8/3n4/3P4/8/8/8/4K3 w - - 0 1");
+        assert!(white_score(&free).1 > white_score(&blocked).1);
+    }
+}
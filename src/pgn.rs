@@ -0,0 +1,340 @@
+use crate::eval::eval_relative;
+use crate::movegen::{in_check, legal_moves, make_move};
+use crate::params::{params, EvalParams};
+use crate::utils::*;
+
+// Building tuning data from games. Positions are taken from the moves of each game and labelled
+// with its result. Only quiet positions are kept, since the static eval can't see tactics and
+// fitting it to positions in the middle of an exchange teaches it the wrong thing.
+
+// How far the capture search looks before taking the static eval as it is
+const QUIESCENCE_DEPTH: u8 = 6;
+// A position isn't quiet if a capture gains more than this, in pawns
+const QUIET_MARGIN: f32 = 0.5;
+
+// One game as read from the file, moves still in SAN
+pub struct PgnGame {
+    // the FEN tag, for games that don't start from the usual position
+    pub fen: Option<String>,
+    // 1 for a White win, 0.5 for a draw, 0 for a Black win. None for unfinished games
+    pub result: Option<f64>,
+    pub moves: Vec<String>,
+}
+
+pub struct ExtractOptions {
+    // plies at the start of each game to leave out, since openings mostly come from a book
+    pub skip_plies: usize,
+    // share of the quiet positions to keep, so one game doesn't give many near copies
+    pub sample_rate: f64,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            skip_plies: 8,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+fn parse_result(token: &str) -> Option<Option<f64>> {
+    match token {
+        "1-0" => Some(Some(1.0)),
+        "0-1" => Some(Some(0.0)),
+        "1/2-1/2" => Some(Some(0.5)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+// Every game in the text. Comments, variations, annotation glyphs and move numbers are skipped.
+pub fn parse_games(text: &str) -> Vec<PgnGame> {
+    let new_game = || PgnGame {
+        fen: None,
+        result: None,
+        moves: Vec::new(),
+    };
+    let mut games = Vec::new();
+    let mut game = new_game();
+    let mut variation_depth = 0;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                if let Some((name, value)) = tag.split_once(' ') {
+                    let value = value.trim().trim_matches('"');
+                    match name {
+                        "FEN" => game.fen = Some(value.to_string()),
+                        "Result" => game.result = parse_result(value).flatten(),
+                        _ => {}
+                    }
+                }
+            }
+            '{' => chars.by_ref().take_while(|&c| c != '}').for_each(drop),
+            ';' => chars.by_ref().take_while(|&c| c != '\n').for_each(drop),
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "[{;()".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                if variation_depth > 0 || token.starts_with('$') {
+                    continue;
+                }
+                if let Some(result) = parse_result(&token) {
+                    game.result = game.result.or(result);
+                    games.push(std::mem::replace(&mut game, new_game()));
+                    continue;
+                }
+                // move numbers, which may run into the move as in 1.e4 or 3...Nf6
+                let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            }
+        }
+    }
+
+    if !game.moves.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+// The legal move a SAN string names, or None if it names none or more than one
+pub fn san_to_move(board: &Board, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let moves = legal_moves(board);
+    let castle = match san {
+        "O-O" | "0-0" => Some(KINGSIDE_CASTLE),
+        "O-O-O" | "0-0-0" => Some(QUEENSIDE_CASTLE),
+        _ => None,
+    };
+    if let Some(kind) = castle {
+        return moves.into_iter().find(|m| m.kind == kind);
+    }
+
+    let mut chars: Vec<char> = san.chars().filter(|&c| !"x:=-".contains(c)).collect();
+    // piece type bits, as in the piece codes without the colour
+    let piece = match chars.first()? {
+        'N' => 0b1000,
+        'B' => 0b10000,
+        'R' => 0b100000,
+        'Q' => 0b1000000,
+        'K' => 0b10000000,
+        _ => 0b100,
+    };
+    if piece != 0b100 {
+        chars.remove(0);
+    }
+    // promotion kinds are 8 to 15 with the piece in the low two bits
+    let promotion = match chars.last() {
+        Some(&letter) if piece == 0b100 && "NBRQ".contains(letter) => {
+            chars.pop();
+            Some("NBRQ".find(letter)? as u8)
+        }
+        _ => None,
+    };
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let destination: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    if !SQUARES.contains(&destination.as_str()) {
+        return None;
+    }
+    let to = square_to_index(&destination);
+    // whatever is left tells apart pieces that could both go there
+    let file = chars.iter().find(|c| c.is_ascii_lowercase());
+    let rank = chars.iter().find(|c| c.is_ascii_digit());
+
+    let mut candidates = moves.into_iter().filter(|m| {
+        m.piece & !0b11 == piece
+            && m.to == to
+            && file.is_none_or(|&f| m.from % 8 == f as u8 - b'a')
+            && rank.is_none_or(|&r| m.from / 8 == r as u8 - b'1')
+            && match promotion {
+                Some(promoted) => m.kind & 0b1000 != 0 && m.kind & 0b11 == promoted,
+                None => m.kind & 0b1000 == 0,
+            }
+    });
+    let found = candidates.next()?;
+    match candidates.next() {
+        Some(_) => None,
+        None => Some(found),
+    }
+}
+
+// Captures and promotions only, from the side to move's point of view, in pawns
+fn quiescence(board: &Board, mut alpha: f32, beta: f32, depth: u8, params: &EvalParams) -> f32 {
+    let stand_pat = eval_relative(board, params);
+    if depth == 0 || stand_pat >= beta {
+        return stand_pat;
+    }
+    alpha = alpha.max(stand_pat);
+
+    // captures, en passant and promotions all have one of these bits set
+    for ply in legal_moves(board)
+        .iter()
+        .filter(|m| m.kind & (CAPTURE | KNIGHT_PROMO) != 0)
+    {
+        let score = -quiescence(&make_move(board, ply), -beta, -alpha, depth - 1, params);
+        if score >= beta {
+            return score;
+        }
+        alpha = alpha.max(score);
+    }
+    alpha
+}
+
+// Whether the static eval can be trusted here: the side to move isn't in check and has no
+// capture that wins anything
+pub fn is_quiet(board: &Board, params: &EvalParams) -> bool {
+    if in_check(board, board.turn) {
+        return false;
+    }
+    let stand_pat = eval_relative(board, params);
+    let best = quiescence(
+        board,
+        f32::NEG_INFINITY,
+        f32::INFINITY,
+        QUIESCENCE_DEPTH,
+        params,
+    );
+    best - stand_pat < QUIET_MARGIN
+}
+
+// The quiet positions of finished games as lines the tuner reads, a FEN and the result such as
+// [1.0]. A game stops being read at a move that doesn't parse, and one whose FEN tag doesn't parse
+// is skipped.
+pub fn extract_positions(games: &[PgnGame], options: &ExtractOptions) -> Vec<String> {
+    let params = params();
+    let mut lines = Vec::new();
+    for game in games {
+        let Some(result) = game.result else {
+            continue;
+        };
+        let mut board = match &game.fen {
+            Some(fen) => match try_fen_to_board(fen) {
+                Some(board) => board,
+                None => continue,
+            },
+            None => starting_position(),
+        };
+
+        for (ply, san) in game.moves.iter().enumerate() {
+            let sampled = options.sample_rate >= 1.0 || fastrand::f64() < options.sample_rate;
+            if ply >= options.skip_plies && sampled && is_quiet(&board, &params) {
+                lines.push(format!("{} [{:.1}]", board_to_fen(&board), result));
+            }
+            match san_to_move(&board, san) {
+                Some(ply) => board = make_move(&board, &ply),
+                None => break,
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, san: &str) -> Option<String> {
+        san_to_move(&fen_to_board(fen), san).map(|m| move_to_uci(&m))
+    }
+
+    #[test]
+    fn reads_san() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(start, "e4").as_deref(), Some("e2e4"));
+        assert_eq!(san(start, "Nf3!").as_deref(), Some("g1f3"));
+        assert_eq!(san(start, "e5"), None);
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san(kiwipete, "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(san(kiwipete, "O-O-O+").as_deref(), Some("e1c1"));
+        assert_eq!(san(kiwipete, "dxe6").as_deref(), Some("d5e6"));
+        assert_eq!(san(kiwipete, "Qxf6").as_deref(), Some("f3f6"));
+
+        // two rooks can reach d1
+        let rooks = "4k3/8/8/8/8/8/4K3/R6R w - - 0 1";
+        assert_eq!(san(rooks, "Rd1"), None);
+        assert_eq!(san(rooks, "Rad1").as_deref(), Some("a1d1"));
+        assert_eq!(san(rooks, "Rhd1").as_deref(), Some("h1d1"));
+
+        let promotion = "8/4P3/8/8/8/8/k7/4K3 w - - 0 1";
+        assert_eq!(san(promotion, "e8=Q").as_deref(), Some("e7e8q"));
+        assert_eq!(san(promotion, "e8N").as_deref(), Some("e7e8n"));
+    }
+
+    #[test]
+    fn reads_games() {
+        let text = r#"[Event "Test"]
+[Result "1-0"]
+
+1. e4 e5 {a comment} 2. Nf3 (2. f4 exf4) Nc6 $1 3.Bb5 ; the Spanish
+3...a6 1-0
+
+[Event "Second"]
+[FEN "4k3/8/8/8/8/8/4K3/R6R w - - 0 1"]
+
+1. Rad1 Kf7 1/2-1/2
+"#;
+        let games = parse_games(text);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].result, Some(1.0));
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6"]);
+        assert_eq!(games[1].result, Some(0.5));
+        assert_eq!(
+            games[1].fen.as_deref(),
+            Some("4k3/8/8/8/8/8/4K3/R6R w - - 0 1")
+        );
+        assert_eq!(games[1].moves, ["Rad1", "Kf7"]);
+
+        let options = ExtractOptions {
+            skip_plies: 2,
+            sample_rate: 1.0,
+        };
+        let lines = extract_positions(&games[..1], &options);
+        assert_eq!(lines.len(), 4);
+        let broken = PgnGame {
+            fen: Some("4k3/8/8 w - - 0 1".to_string()),
+            result: Some(0.5),
+            moves: vec!["Kd7".to_string()],
+        };
+        assert!(extract_positions(&[broken], &options).is_empty());
+        assert_eq!(
+            lines[0],
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2 [1.0]"
+        );
+    }
+
+    #[test]
+    fn quiet_positions() {
+        let params = params();
+        assert!(is_quiet(&starting_position(), &params));
+        // the queen can just be taken
+        assert!(!is_quiet(
+            &fen_to_board("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1"),
+            &params
+        ));
+        // taking it loses the rook back, so there is nothing to win
+        assert!(is_quiet(
+            &fen_to_board("4k3/2p5/3p4/8/8/8/3R4/4K3 w - - 0 1"),
+            &params
+        ));
+        assert!(!is_quiet(
+            &fen_to_board("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1"),
+            &params
+        ));
+    }
+}
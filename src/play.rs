@@ -55,15 +55,19 @@ pub fn play_game(board_param: &Board) {
             .expect("failed to readline");
 
         let re = Regex::new(r"^([A-Ha-h][1-8]){2}[nbrqNBRQ]?$").unwrap();
+        let options = SearchOptions::default();
+        let mut history: Vec<u64> = Vec::new();
 
         while user_move.trim() != "quit" {
             // make sure move is valid syntax
             if re.is_match(user_move.trim()) {
                 // make sure move is legal
+                let before = zobrist_hash(&board);
                 if make_user_move(&user_move, &mut board) {
+                    history.push(before);
 
                     // now make engine move
-                    let (best, eval) = best_move(&board, depth);
+                    let (best, eval, line, _) = best_move(&board, depth, &history, &options);
 
                     if let Some(unwrapped) = best {
                         println!(
@@ -72,9 +76,13 @@ pub fn play_game(board_param: &Board) {
                             index_to_square(unwrapped.to)
                         );
                         println!("Eval: {eval}");
+                        let expected: Vec<String> = line.iter().map(move_to_uci).collect();
+                        println!("Expected line: {}", expected.join(" "));
+                        history.push(zobrist_hash(&board));
                         board = make_move(&board, &unwrapped);
 
-                        let (user_option, user_eval) = best_move(&board, 1);
+                        let (user_option, user_eval, _, _) =
+                            best_move(&board, 1, &history, &options);
                         if user_option.is_none() {
                             ending(eval, false);
                             break;
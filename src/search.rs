@@ -1,20 +1,393 @@
+use crate::endgame::is_dead_draw;
 use crate::eval::*;
+use crate::hash::zobrist_hash;
+use crate::movegen::in_check;
 use crate::movegen::legal_moves;
 use crate::movegen::make_move;
+use crate::nnue::{Accumulator, Network};
+use crate::params::{params, EvalParams};
+use crate::syzygy::{Tablebases, LOSS, WIN};
 use crate::utils::*;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-// still need to account for checkmate and stalemate
+// Internal iterative deepening. There is no hash move to try first, so PV nodes at least this
+// deep are searched IID_REDUCTION plies shallower first and the best move from that search leads.
+const IID_MIN_DEPTH: u8 = 4;
+const IID_REDUCTION: u8 = 2;
+// A window no wider than this, in pawns, only asks whether the score is above alpha. Nodes with
+// a wider one are PV nodes
+const NULL_WINDOW: f32 = 0.01;
+
+// Score in pawns for a position the tablebases say is won. Below mate, which is infinite.
+const TB_WIN: f32 = 200.0;
+
+// Deepest a node limited search goes however small the tree is
+const MAX_DEPTH: u8 = 64;
+
+// Triangular principal variation table. lines[height] holds the best line found so far from the
+// node at that distance from the root. When a move becomes the best at some height, its line is
+// that move followed by the line one height further down.
+pub struct PvTable {
+    lines: Vec<Vec<Move>>,
+}
+
+impl PvTable {
+    pub fn new() -> PvTable {
+        PvTable { lines: Vec::new() }
+    }
+
+    // Called on entry to a node so a stale line from a sibling can't leak into this one
+    fn clear(&mut self, height: usize) {
+        if self.lines.len() <= height + 1 {
+            self.lines.resize_with(height + 2, Vec::new);
+        }
+        self.lines[height].clear();
+    }
+
+    fn update(&mut self, height: usize, ply: &Move) {
+        let (above, below) = self.lines.split_at_mut(height + 1);
+        let line = &mut above[height];
+        line.clear();
+        line.push(ply.clone());
+        line.extend_from_slice(&below[0]);
+    }
+
+    pub fn line(&self, height: usize) -> &[Move] {
+        match self.lines.get(height) {
+            Some(line) => line,
+            None => &[],
+        }
+    }
+}
+
+// Engine settings the search reads, set through UCI options
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    // How many centipawns the engine thinks a draw is worse for itself than for its opponent
+    pub contempt: i32,
+    // Evaluate with the network instead of the handcrafted eval, when one is loaded
+    pub use_nnue: bool,
+    pub network: Option<Arc<Network>>,
+    // Syzygy tables from the SyzygyPath option
+    pub tablebases: Option<Arc<Tablebases>>,
+}
+
+// Counters collected while searching. There is no quiescence search, transposition table, null
+// move or late move reduction yet, so there is nothing to count for those.
+#[derive(Clone, Debug, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    // static evaluations at the horizon
+    pub evals: u64,
+    // evals answered by the evaluation cache
+    pub eval_cache_hits: u64,
+    pub beta_cutoffs: u64,
+    // cutoffs caused by the first move searched, a measure of move ordering
+    pub first_move_cutoffs: u64,
+    pub iid_searches: u64,
+    // repetitions and 50 move draws found in the tree
+    pub draws: u64,
+    // positions scored by the tablebases
+    pub tb_hits: u64,
+    // nodes_per_height[h] counts nodes h plies from the root
+    pub nodes_per_height: Vec<u64>,
+    // depth of the deepest search that finished
+    pub depth: u8,
+    pub time: Duration,
+}
+
+impl SearchStats {
+    fn node(&mut self, height: usize) {
+        self.nodes += 1;
+        if self.nodes_per_height.len() <= height {
+            self.nodes_per_height.resize(height + 1, 0);
+        }
+        self.nodes_per_height[height] += 1;
+    }
+
+    // Adds in the counts of another search of the same position
+    fn add(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.evals += other.evals;
+        self.eval_cache_hits += other.eval_cache_hits;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.iid_searches += other.iid_searches;
+        self.draws += other.draws;
+        self.tb_hits += other.tb_hits;
+        if self.nodes_per_height.len() < other.nodes_per_height.len() {
+            self.nodes_per_height
+                .resize(other.nodes_per_height.len(), 0);
+        }
+        for (total, nodes) in self
+            .nodes_per_height
+            .iter_mut()
+            .zip(&other.nodes_per_height)
+        {
+            *total += nodes;
+        }
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.time.as_secs_f64();
+        let nps = if seconds > 0.0 {
+            (self.nodes as f64 / seconds) as u64
+        } else {
+            0
+        };
+        writeln!(
+            f,
+            "nodes {} (evals {}) in {:.3}s, {} nps",
+            self.nodes, self.evals, seconds, nps
+        )?;
+
+        let per_height: Vec<String> = self
+            .nodes_per_height
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        writeln!(f, "nodes by ply: {}", per_height.join(" "))?;
+
+        // how many times more nodes each ply has than the one before it
+        let branching: Vec<String> = self
+            .nodes_per_height
+            .windows(2)
+            .map(|pair| format!("{:.2}", pair[1] as f64 / pair[0] as f64))
+            .collect();
+        writeln!(f, "branching factor by ply: {}", branching.join(" "))?;
+
+        let first_move = if self.beta_cutoffs > 0 {
+            100.0 * self.first_move_cutoffs as f64 / self.beta_cutoffs as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "beta cutoffs {}, {:.1}% on the first move",
+            self.beta_cutoffs, first_move
+        )?;
+        let cache_hits = if self.evals > 0 {
+            100.0 * self.eval_cache_hits as f64 / self.evals as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "eval cache hits {}, {:.1}% of evals",
+            self.eval_cache_hits, cache_hits
+        )?;
+        writeln!(f, "iid searches {}", self.iid_searches)?;
+        writeln!(f, "draws found {}", self.draws)?;
+        write!(f, "tablebase hits {}", self.tb_hits)
+    }
+}
+
+// State shared by every node of one search
+struct SearchInfo {
+    pv: PvTable,
+    stats: SearchStats,
+    // Zobrist hashes of the game so far followed by the positions on the current search path
+    history: Vec<u64>,
+    // The weights taken when the search started
+    params: Arc<EvalParams>,
+    // What a drawn position is worth from White's point of view
+    draw_score: f32,
+    // Set when evaluating with the network. accumulators[height] belongs to the node at that
+    // height on the current path.
+    network: Option<Arc<Network>>,
+    accumulators: Vec<Accumulator>,
+    tablebases: Option<Arc<Tablebases>>,
+    // The search stops once it has visited this many nodes, and stopped is set so every node on
+    // the path returns without its score being used
+    node_limit: u64,
+    stopped: bool,
+}
+
+impl SearchInfo {
+    fn new(
+        board: &Board,
+        history: &[u64],
+        options: &SearchOptions,
+        params: Arc<EvalParams>,
+        node_limit: u64,
+    ) -> SearchInfo {
+        let mut info = SearchInfo {
+            pv: PvTable::new(),
+            stats: SearchStats::default(),
+            history: history.to_vec(),
+            params,
+            draw_score: draw_score(board.turn, options.contempt),
+            network: options.network.clone().filter(|_| options.use_nnue),
+            accumulators: Vec::new(),
+            tablebases: options.tablebases.clone(),
+            node_limit,
+            stopped: false,
+        };
+        if let Some(network) = &info.network {
+            info.accumulators.push(network.refresh(board));
+        }
+        info
+    }
+}
+
+// Static evaluation of the node at the given height, looked up in the evaluation cache first
+fn evaluate(board: &Board, hash: u64, info: &mut SearchInfo, height: usize) -> f32 {
+    info.stats.evals += 1;
+    if let Some(score) = probe_eval_cache(hash) {
+        info.stats.eval_cache_hits += 1;
+        return score;
+    }
+    let score = match &info.network {
+        Some(network) => network.evaluate(&info.accumulators[height], board.turn) as f32 / 100.0,
+        None => eval(board, &info.params),
+    };
+    store_eval_cache(hash, score);
+    score
+}
+
+// Brings the accumulator one height down in line with the move about to be searched
+fn update_accumulator(info: &mut SearchInfo, height: usize, board: &Board, ply: &Move) {
+    if let Some(network) = &info.network {
+        network.update(&mut info.accumulators, height, board, ply);
+    }
+}
+
+// Contempt belongs to the side to move at the root, so the score is fixed for the whole search
+// rather than flipping with the side to move at each node
+fn draw_score(root_turn: bool, contempt: i32) -> f32 {
+    let contempt = contempt as f32 / 100.0;
+    if root_turn {
+        -contempt
+    } else {
+        contempt
+    }
+}
+
+// 50 move rule, a position that already occurred since the last capture or pawn move, or too
+// little material left to mate
+pub fn is_draw(board: &Board, hash: u64, history: &[u64]) -> bool {
+    board.halfmove >= 100
+        || is_dead_draw(board)
+        || history
+            .iter()
+            .rev()
+            .take(board.halfmove as usize)
+            .any(|&previous| previous == hash)
+}
+
+// Tablebase score from White's point of view. Only probed right after a capture or pawn move, when
+// the 50 move count is back to zero so the tables' result is the true one.
+fn probe_tablebases(board: &Board, info: &mut SearchInfo) -> Option<f32> {
+    let tablebases = info.tablebases.as_ref()?;
+    if board.halfmove != 0 || !tablebases.covers(board) {
+        return None;
+    }
+    let wdl = tablebases.probe_wdl(board)?;
+    info.stats.tb_hits += 1;
+    let score = match wdl {
+        WIN => TB_WIN,
+        LOSS => -TB_WIN,
+        _ => return Some(info.draw_score),
+    };
+    Some(if board.turn { score } else { -score })
+}
+
+// Score of a position with no legal moves
+fn game_over_score(board: &Board, draw_score: f32) -> f32 {
+    if in_check(board, board.turn) {
+        if board.turn {
+            f32::NEG_INFINITY
+        } else {
+            f32::INFINITY
+        }
+    } else {
+        draw_score
+    }
+}
+
+// Returns the best move, its evaluation, the principal variation starting with that move, and
+// statistics about the search. history holds the Zobrist hashes of the positions played before
+// this one, used to spot repetitions.
 pub fn best_move(
     board: &Board,
-    depth: u8) -> (Option<Move>, f32) {
-    let mut possibilities = legal_moves(board);
+    depth: u8,
+    history: &[u64],
+    options: &SearchOptions,
+) -> (Option<Move>, f32, Vec<Move>, SearchStats) {
+    let start = Instant::now();
+    let params = params();
+    let possibilities = root_moves(board, options, &params);
 
     // check for stalemate or checkmate
-    if possibilities.len() == 0 {
-        return (None, eval(board));
+    if possibilities.is_empty() {
+        // the game is over, so a stalemate is just a draw
+        let score = game_over_score(board, 0.0);
+        return (None, score, Vec::new(), SearchStats::default());
+    }
+
+    let mut info = SearchInfo::new(board, history, options, params, u64::MAX);
+    // without a node limit the search always finishes
+    let (i, best, line) = search_root(board, &possibilities, depth, &mut info).unwrap();
+    info.stats.depth = depth;
+    info.stats.time = start.elapsed();
+
+    (Some(possibilities[i].clone()), best, line, info.stats)
+}
+
+// Like best_move, but deepens one ply at a time until the search has visited the given number of
+// nodes, then returns the result of the deepest search that finished. The search to depth 1
+// always finishes so there is a move to play.
+pub fn search_nodes(
+    board: &Board,
+    nodes: u64,
+    history: &[u64],
+    options: &SearchOptions,
+) -> (Option<Move>, f32, Vec<Move>, SearchStats) {
+    let start = Instant::now();
+    let params = params();
+    let possibilities = root_moves(board, options, &params);
+    if possibilities.is_empty() {
+        let score = game_over_score(board, 0.0);
+        return (None, score, Vec::new(), SearchStats::default());
     }
 
+    let mut stats = SearchStats::default();
+    let mut result = None;
+    for depth in 1..=MAX_DEPTH {
+        let node_limit = if depth == 1 {
+            u64::MAX
+        } else {
+            nodes.saturating_sub(stats.nodes)
+        };
+        let mut info = SearchInfo::new(board, history, options, params.clone(), node_limit);
+        let finished = search_root(board, &possibilities, depth, &mut info);
+        stats.add(&info.stats);
+        let Some((i, score, line)) = finished else {
+            break;
+        };
+        stats.depth = depth;
+        result = Some((i, score, line));
+        // a deeper search can't improve on a mate
+        if score.is_infinite() || stats.nodes >= nodes {
+            break;
+        }
+    }
+
+    let (i, score, line) = result.unwrap();
+    stats.time = start.elapsed();
+    (Some(possibilities[i].clone()), score, line, stats)
+}
+
+// The legal moves in the order the root searches them, leaving out those the tablebases say
+// throw away the result
+fn root_moves(board: &Board, options: &SearchOptions, params: &EvalParams) -> Vec<Move> {
+    let mut possibilities = legal_moves(board);
+
     // do higher impact moves first
     possibilities.sort_by_key(|m| std::cmp::Reverse(m.kind));
 
@@ -34,41 +407,67 @@ pub fn best_move(
         }
     }
 
-    // make moves closer to center
-    possibilities.sort_by_key(|m| {
-        let file = m.to % 8;
-        let dist_to_d = (file as i8 - 3).abs();
-        let dist_to_e = (file as i8 - 4).abs();
-        dist_to_d.min(dist_to_e)
+    // make moves that gain space and control the centre
+    possibilities.sort_by_cached_key(|m| {
+        std::cmp::Reverse(space_score(&make_move(board, m), board.turn, params))
     });
 
     // move less valuable pieces first
     possibilities.sort_by_key(|m| m.piece);
 
+    // in a tablebase position only the moves that keep the best result are searched
+    if let Some(tablebases) = &options.tablebases {
+        if let Some(moves) = tablebases.root_moves(board, &possibilities) {
+            possibilities = moves;
+        }
+    }
+    possibilities
+}
+
+// Searches every root move to the given depth. Returns the index of the best one, its evaluation
+// and its line, or None if the node limit stopped the search first.
+fn search_root(
+    board: &Board,
+    possibilities: &[Move],
+    depth: u8,
+    info: &mut SearchInfo,
+) -> Option<(usize, f32, Vec<Move>)> {
+    if info.stats.nodes >= info.node_limit {
+        return None;
+    }
     let mut evaluations = Vec::new();
+    let mut lines = Vec::new();
+    info.history.push(zobrist_hash(board));
+    info.stats.node(0);
 
     for ply in possibilities.iter() {
         let made_move = make_move(board, &ply);
-                   // evaluations.push(best_move_helper(&made_move, depth - 1));
-            // CHANGE THE FOLLOWING LINE TODO
-            let evaluation = if board.turn {
-                (ab_min(
-                    &made_move,
-                    f32::NEG_INFINITY,
-                    f32::INFINITY,
-                    depth - 1,
-                                  ))
-            } else {
-                ab_max(
-                    &made_move,
-                    f32::NEG_INFINITY,
-                    f32::INFINITY,
-                    depth - 1,
-                                   )
-            
+        update_accumulator(info, 0, board, ply);
+        let evaluation = if board.turn {
+            ab_min(
+                &made_move,
+                f32::NEG_INFINITY,
+                f32::INFINITY,
+                depth - 1,
+                info,
+                1,
+            )
+        } else {
+            ab_max(
+                &made_move,
+                f32::NEG_INFINITY,
+                f32::INFINITY,
+                depth - 1,
+                info,
+                1,
+            )
         };
+        if info.stopped {
+            return None;
+        }
         evaluations.push(evaluation);
-            }
+        lines.push(info.pv.line(1).to_vec());
+    }
 
     let mut best = if board.turn {
         f32::NEG_INFINITY
@@ -98,79 +497,217 @@ pub fn best_move(
         i += 1;
     }
 
-    (Some(possibilities[i].clone()), best)
+    let mut line = vec![possibilities[i].clone()];
+    line.extend(lines.swap_remove(i));
+
+    Some((i, best, line))
 }
 
 fn ab_max(
     board: &Board,
-    mut alpha: f32,
+    alpha: f32,
     beta: f32,
-    depth: u8) -> f32 {
-    if depth == 0 {
-        return eval(board);
+    depth: u8,
+    info: &mut SearchInfo,
+    height: usize,
+) -> f32 {
+    info.pv.clear(height);
+    if info.stats.nodes >= info.node_limit {
+        info.stopped = true;
+        return 0.0;
     }
-    let mut best = f32::NEG_INFINITY;
+    info.stats.node(height);
+    let hash = zobrist_hash(board);
     let possibilities = legal_moves(board);
 
-    // check for stalemate or checkmate
-    if possibilities.len() == 0 {
-        return eval(board);
+    // check for stalemate or checkmate. A mate stands even when it comes on the 100th half move
+    if possibilities.is_empty() {
+        return game_over_score(board, info.draw_score);
+    }
+    if is_draw(board, hash, &info.history) {
+        info.stats.draws += 1;
+        return info.draw_score;
+    }
+    if let Some(score) = probe_tablebases(board, info) {
+        return score;
+    }
+    if depth == 0 {
+        return evaluate(board, hash, info, height);
     }
 
-    for ply in possibilities.into_iter() {
+    info.history.push(hash);
+    let best = ab_max_moves(board, alpha, beta, depth, info, height, possibilities);
+    info.history.pop();
+    best
+}
+
+// The move loop of ab_max, for a node already counted and checked. Internal iterative
+// deepening runs it again at a shallower depth for the same node, so that search isn't counted as
+// another node.
+fn ab_max_moves(
+    board: &Board,
+    mut alpha: f32,
+    beta: f32,
+    depth: u8,
+    info: &mut SearchInfo,
+    height: usize,
+    mut possibilities: Vec<Move>,
+) -> f32 {
+    // only a PV node, where the window is open, is worth a search just to find its first move
+    if depth >= IID_MIN_DEPTH && beta - alpha > NULL_WINDOW {
+        info.stats.iid_searches += 1;
+        ab_max_moves(
+            board,
+            alpha,
+            beta,
+            depth - IID_REDUCTION,
+            info,
+            height,
+            possibilities.clone(),
+        );
+        if info.stopped {
+            return 0.0;
+        }
+        if let Some(first) = info.pv.line(height).first().cloned() {
+            move_to_front(&mut possibilities, &first);
+        }
+        info.pv.clear(height);
+    }
+
+    let mut best = f32::NEG_INFINITY;
+    for (i, ply) in possibilities.into_iter().enumerate() {
         let made_move = make_move(board, &ply);
+        update_accumulator(info, height, board, &ply);
+
+        let score = ab_min(&made_move, alpha, beta, depth - 1, info, height + 1);
+        if info.stopped {
+            return 0.0;
+        }
 
-        
-       
-          let  score = ab_min(&made_move, alpha, beta, depth - 1);
-        
         if score > best {
             best = score;
             if score > alpha {
                 alpha = score;
+                info.pv.update(height, &ply);
             }
         }
         if alpha >= beta {
-            return beta;
-        }
+            info.stats.beta_cutoffs += 1;
+            if i == 0 {
+                info.stats.first_move_cutoffs += 1;
             }
+            best = beta;
+            break;
+        }
+    }
     best
 }
 fn ab_min(
     board: &Board,
     alpha: f32,
-    mut beta: f32,
-    depth: u8) -> f32 {
-    if depth == 0 {
-        return eval(board);
+    beta: f32,
+    depth: u8,
+    info: &mut SearchInfo,
+    height: usize,
+) -> f32 {
+    info.pv.clear(height);
+    if info.stats.nodes >= info.node_limit {
+        info.stopped = true;
+        return 0.0;
     }
-    let mut best = f32::INFINITY;
+    info.stats.node(height);
+    let hash = zobrist_hash(board);
     let possibilities = legal_moves(board);
 
-    // check for stalemate or checkmate
-    if possibilities.len() == 0 {
-        return eval(board);
+    // check for stalemate or checkmate. A mate stands even when it comes on the 100th half move
+    if possibilities.is_empty() {
+        return game_over_score(board, info.draw_score);
+    }
+    if is_draw(board, hash, &info.history) {
+        info.stats.draws += 1;
+        return info.draw_score;
+    }
+    if let Some(score) = probe_tablebases(board, info) {
+        return score;
+    }
+    if depth == 0 {
+        return evaluate(board, hash, info, height);
     }
 
-    for ply in possibilities.into_iter() {
+    info.history.push(hash);
+    let best = ab_min_moves(board, alpha, beta, depth, info, height, possibilities);
+    info.history.pop();
+    best
+}
+
+// The move loop of ab_min, for a node already counted and checked. Internal iterative
+// deepening runs it again at a shallower depth for the same node, so that search isn't counted as
+// another node.
+fn ab_min_moves(
+    board: &Board,
+    alpha: f32,
+    mut beta: f32,
+    depth: u8,
+    info: &mut SearchInfo,
+    height: usize,
+    mut possibilities: Vec<Move>,
+) -> f32 {
+    // only a PV node, where the window is open, is worth a search just to find its first move
+    if depth >= IID_MIN_DEPTH && beta - alpha > NULL_WINDOW {
+        info.stats.iid_searches += 1;
+        ab_min_moves(
+            board,
+            alpha,
+            beta,
+            depth - IID_REDUCTION,
+            info,
+            height,
+            possibilities.clone(),
+        );
+        if info.stopped {
+            return 0.0;
+        }
+        if let Some(first) = info.pv.line(height).first().cloned() {
+            move_to_front(&mut possibilities, &first);
+        }
+        info.pv.clear(height);
+    }
+
+    let mut best = f32::INFINITY;
+    for (i, ply) in possibilities.into_iter().enumerate() {
         let made_move = make_move(board, &ply);
+        update_accumulator(info, height, board, &ply);
+
+        let score = ab_max(&made_move, alpha, beta, depth - 1, info, height + 1);
+        if info.stopped {
+            return 0.0;
+        }
 
-       
-    
-          let score = ab_max(&made_move, alpha, beta, depth - 1);
-        
         if score < best {
             best = score;
             if score < beta {
                 beta = score;
+                info.pv.update(height, &ply);
             }
         }
         if beta <= alpha {
-            return alpha;
+            info.stats.beta_cutoffs += 1;
+            if i == 0 {
+                info.stats.first_move_cutoffs += 1;
+            }
+            best = alpha;
+            break;
         }
-           }
+    }
     best
 }
+// Puts the given move at the front of the list, keeping the order of the rest
+fn move_to_front(possibilities: &mut [Move], ply: &Move) {
+    if let Some(index) = possibilities.iter().position(|m| m == ply) {
+        possibilities[..=index].rotate_right(1);
+    }
+}
+
 /*
 fn best_move_helper(board: &Board, depth: u8) -> f32 {
     if depth == 0 {
@@ -214,4 +751,114 @@ fn best_move_helper(board: &Board, depth: u8) -> f32 {
 mod tests {
     use super::*;
     use crate::utils::*;
+
+    #[test]
+    fn pv_is_playable() {
+        let board = starting_position();
+        let (best, _, line, _) = best_move(&board, 3, &[], &SearchOptions::default());
+        assert_eq!(line.len(), 3);
+        assert_eq!(Some(line[0].clone()), best);
+
+        let mut position = board;
+        for ply in line.iter() {
+            assert!(legal_moves(&position).contains(ply));
+            position = make_move(&position, ply);
+        }
+    }
+
+    #[test]
+    fn stats_count_nodes() {
+        let board = starting_position();
+        let (_, _, _, stats) = best_move(&board, 2, &[], &SearchOptions::default());
+        assert_eq!(stats.nodes_per_height[..2], [1, 20]);
+        assert_eq!(stats.nodes, stats.nodes_per_height.iter().sum::<u64>());
+        assert_eq!(stats.evals, stats.nodes_per_height[2]);
+        assert!(stats.first_move_cutoffs <= stats.beta_cutoffs);
+    }
+
+    #[test]
+    fn node_limit() {
+        let board = starting_position();
+        let options = SearchOptions::default();
+        let (small_best, _, _, small) = search_nodes(&board, 2000, &[], &options);
+        let (large_best, _, _, large) = search_nodes(&board, 50000, &[], &options);
+        assert!(small.nodes <= 2000);
+        assert!(large.nodes <= 50000);
+        assert!(small_best.is_some() && large_best.is_some());
+        assert!(large.depth > small.depth);
+
+        // the last search that finished is the one whose move is played
+        let (best, _, _, _) = best_move(&board, large.depth, &[], &options);
+        assert_eq!(best, large_best);
+    }
+
+    #[test]
+    fn mate_on_the_hundredth_half_move() {
+        // Ra8 is mate and also the 100th half move without a capture or pawn move
+        let board = fen_to_board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80");
+        let (best, evaluation, _, _) = best_move(&board, 2, &[], &SearchOptions::default());
+        assert_eq!(move_to_uci(&best.unwrap()), "a1a8");
+        assert_eq!(evaluation, f32::INFINITY);
+    }
+
+    #[test]
+    fn contempt_from_root_side() {
+        // Every move reaches the 50 move limit, so the whole tree is a draw
+        let mut board = fen_to_board("4k3/8/8/8/8/8/8/R3K3 w - - 99 80");
+        let options = SearchOptions {
+            contempt: 30,
+            ..SearchOptions::default()
+        };
+        let (_, evaluation, _, _) = best_move(&board, 2, &[], &options);
+        assert_eq!(evaluation, -0.3);
+
+        board.turn = false;
+        let (_, evaluation, _, _) = best_move(&board, 2, &[], &options);
+        assert_eq!(evaluation, 0.3);
+
+        let (_, evaluation, _, _) = best_move(&board, 2, &[], &SearchOptions::default());
+        assert_eq!(evaluation, 0.0);
+    }
+
+    #[test]
+    fn repetition_is_draw() {
+        let board = starting_position();
+        let hash = zobrist_hash(&board);
+        let mut later = board.clone();
+        later.halfmove = 4;
+        assert!(is_draw(&later, hash, &[hash, 1, 2, 3]));
+        later.halfmove = 3;
+        assert!(!is_draw(&later, hash, &[hash, 1, 2, 3]));
+    }
+
+    #[test]
+    fn front_move() {
+        let mut possibilities = legal_moves(&starting_position());
+        let last = possibilities.last().unwrap().clone();
+        let second = possibilities[1].clone();
+        move_to_front(&mut possibilities, &last);
+        assert_eq!(possibilities[0], last);
+        assert_eq!(possibilities[2], second);
+        assert_eq!(possibilities.len(), 20);
+    }
+
+    #[test]
+    fn iid_finds_mate() {
+        // 1. Kb6 Kb8 2. Rh8#, deep enough that the inner nodes run a shallower search first
+        let board = fen_to_board("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let (_, evaluation, _, stats) = best_move(&board, 5, &[], &SearchOptions::default());
+        assert_eq!(evaluation, f32::INFINITY);
+        // the shallower search runs at the same node rather than counting it again
+        assert!(stats.iid_searches > 0);
+        assert_eq!(stats.nodes_per_height[1], legal_moves(&board).len() as u64);
+    }
+
+    #[test]
+    fn pv_ends_in_mate() {
+        let board = fen_to_board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let (best, evaluation, line, _) = best_move(&board, 3, &[], &SearchOptions::default());
+        assert_eq!(evaluation, f32::INFINITY);
+        assert_eq!(line.len(), 1);
+        assert_eq!(move_to_uci(&best.unwrap()), "a1a8");
+    }
 }
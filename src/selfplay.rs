@@ -0,0 +1,344 @@
+use crate::hash::zobrist_hash;
+use crate::movegen::{in_check, legal_moves, make_move};
+use crate::search::*;
+use crate::utils::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+// Training data from games Ike plays against itself. Each game starts with a few random moves so
+// games don't repeat, then both sides search to a node budget. Every quiet position is kept with
+// the search score and, once the game ends, its result.
+
+// Games still going after this many plies are called drawn
+const MAX_PLIES: usize = 400;
+// A side this many pawns up for ADJUDICATE_PLIES plies in a row is taken to have won
+const ADJUDICATE_SCORE: f32 = 10.0;
+const ADJUDICATE_PLIES: usize = 6;
+
+// Each position takes this many bytes in the binary format
+pub const RECORD_SIZE: usize = 32;
+
+pub struct SelfPlayOptions {
+    pub games: usize,
+    pub threads: usize,
+    // nodes each move's search may use
+    pub nodes: u64,
+    // random plies before the engine takes over
+    pub random_plies: usize,
+    pub search: SearchOptions,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> SelfPlayOptions {
+        SelfPlayOptions {
+            games: 100,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            nodes: 5000,
+            random_plies: 8,
+            search: SearchOptions::default(),
+        }
+    }
+}
+
+// One position of a finished game
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub board: Board,
+    // centipawns from White's point of view
+    pub score: i16,
+    // 1 for a White win, 0.5 for a draw, 0 for a Black win
+    pub result: f32,
+}
+
+impl Record {
+    // fen | score | result
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} | {} | {:.1}",
+            board_to_fen(&self.board),
+            self.score,
+            self.result
+        )
+    }
+
+    // The occupied squares, then a four bit piece code for each of them in square order with
+    // White's pawns to king as 0 to 5 and Black's as 6 to 11, side to move and castling rights,
+    // en passant square (64 for none), 50 move count, move number, score and result as 0, 1 or 2
+    // for a Black win, a draw or a White win. Numbers are little endian.
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let board = &self.board;
+        let occupied = board.white | board.black;
+        let pieces = piece_bitboards(board);
+
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&occupied.to_le_bytes());
+        for (i, square) in set_bit_positions(occupied).into_iter().enumerate().take(32) {
+            let code = pieces
+                .iter()
+                .position(|pieces| pieces & (1 << square) != 0)
+                .unwrap() as u8;
+            bytes[8 + i / 2] |= code << (4 * (i % 2));
+        }
+        bytes[24] = board.turn as u8
+            | (board.white_kingside_castle as u8) << 1
+            | (board.white_queenside_castle as u8) << 2
+            | (board.black_kingside_castle as u8) << 3
+            | (board.black_queenside_castle as u8) << 4;
+        bytes[25] = board.ep_target.unwrap_or(64);
+        bytes[26] = board.halfmove.min(255) as u8;
+        bytes[27..29].copy_from_slice(&board.fullmove.to_le_bytes());
+        bytes[29..31].copy_from_slice(&self.score.to_le_bytes());
+        bytes[31] = (self.result * 2.0).round() as u8;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Record {
+        let occupied = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let mut pieces = [0u64; 12];
+        for (i, square) in set_bit_positions(occupied).into_iter().enumerate().take(32) {
+            let code = (bytes[8 + i / 2] >> (4 * (i % 2))) & 0xf;
+            pieces[code as usize] |= 1 << square;
+        }
+
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            for file in 0..8 {
+                let square = 1u64 << (8 * rank + file);
+                match pieces.iter().position(|pieces| pieces & square != 0) {
+                    Some(code) => fen.push(b"PNBRQKpnbrqk"[code] as char),
+                    None => fen.push('1'),
+                }
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+        // the counters and flags are put back below rather than spelled out in the FEN
+        let mut board = fen_to_board(&format!("{fen} w - - 0 1"));
+        board.turn = bytes[24] & 1 != 0;
+        board.white_kingside_castle = bytes[24] & 2 != 0;
+        board.white_queenside_castle = bytes[24] & 4 != 0;
+        board.black_kingside_castle = bytes[24] & 8 != 0;
+        board.black_queenside_castle = bytes[24] & 16 != 0;
+        board.ep_target = (bytes[25] < 64).then_some(bytes[25]);
+        board.halfmove = bytes[26] as u16;
+        board.fullmove = u16::from_le_bytes([bytes[27], bytes[28]]);
+
+        Record {
+            board,
+            score: i16::from_le_bytes([bytes[29], bytes[30]]),
+            result: bytes[31] as f32 / 2.0,
+        }
+    }
+}
+
+// The twelve piece bitboards in the order of the binary format's piece codes
+fn piece_bitboards(board: &Board) -> [u64; 12] {
+    [
+        board.white_pawn,
+        board.white_knight,
+        board.white_bishop,
+        board.white_rook,
+        board.white_queen,
+        board.white_king,
+        board.black_pawn,
+        board.black_knight,
+        board.black_bishop,
+        board.black_rook,
+        board.black_queen,
+        board.black_king,
+    ]
+}
+
+// Plays one game and returns its positions, or None if the random opening ended the game
+pub fn play_game(options: &SelfPlayOptions) -> Option<Vec<Record>> {
+    let mut board = starting_position();
+    let mut history = Vec::new();
+    for _ in 0..options.random_plies {
+        let moves = legal_moves(&board);
+        if moves.is_empty() {
+            return None;
+        }
+        history.push(zobrist_hash(&board));
+        board = make_move(&board, &moves[fastrand::usize(..moves.len())]);
+    }
+
+    let mut records = Vec::new();
+    let mut winning = (0, true);
+    let result = loop {
+        let hash = zobrist_hash(&board);
+        // a mate stands even on the move that would draw the game
+        let game_over = legal_moves(&board).is_empty();
+        if !game_over && (history.len() >= MAX_PLIES || is_draw(&board, hash, &history)) {
+            break 0.5;
+        }
+        let (best, score, _, _) = search_nodes(&board, options.nodes, &history, &options.search);
+        let Some(best) = best else {
+            // checkmate or stalemate
+            break match score {
+                s if s > 0.0 => 1.0,
+                s if s < 0.0 => 0.0,
+                _ => 0.5,
+            };
+        };
+
+        // positions where the best move is a capture or the side to move is in check say more
+        // about tactics than about the position
+        if score.is_finite() && best.kind & CAPTURE == 0 && !in_check(&board, board.turn) {
+            records.push(Record {
+                board: board.clone(),
+                score: (score * 100.0).round().clamp(-32000.0, 32000.0) as i16,
+                result: 0.0,
+            });
+        }
+
+        if score.abs() >= ADJUDICATE_SCORE {
+            let white = score > 0.0;
+            winning = if winning.1 == white {
+                (winning.0 + 1, white)
+            } else {
+                (1, white)
+            };
+            if winning.0 >= ADJUDICATE_PLIES {
+                break if white { 1.0 } else { 0.0 };
+            }
+        } else {
+            winning.0 = 0;
+        }
+
+        history.push(hash);
+        board = make_move(&board, &best);
+    };
+
+    for record in records.iter_mut() {
+        record.result = result;
+    }
+    Some(records)
+}
+
+// Plays the games across threads, writing positions to both files as games finish. Returns how
+// many positions were written.
+pub fn generate(options: &SelfPlayOptions, binary: &str, text: &str) -> io::Result<usize> {
+    let mut binary = BufWriter::new(File::create(binary)?);
+    let mut text = BufWriter::new(File::create(text)?);
+    let started = AtomicUsize::new(0);
+    // set when the files can't be written, so the workers don't play out the run for nothing
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            let sender = sender.clone();
+            let (started, stop) = (&started, &stop);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed)
+                    && started.fetch_add(1, Ordering::Relaxed) < options.games
+                {
+                    // a game ended by its random opening is replaced by another
+                    let records = loop {
+                        if let Some(records) = play_game(options) {
+                            break records;
+                        }
+                    };
+                    if sender.send(records).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let written = write_records(receiver, &mut binary, &mut text, options.games);
+        if written.is_err() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        written
+    })
+}
+
+// Writes each game's positions as they arrive until every worker is done. Returns how many
+// positions were written.
+fn write_records(
+    receiver: mpsc::Receiver<Vec<Record>>,
+    binary: &mut impl Write,
+    text: &mut impl Write,
+    games: usize,
+) -> io::Result<usize> {
+    let mut positions = 0;
+    for (game, records) in receiver.iter().enumerate() {
+        for record in records.iter() {
+            binary.write_all(&record.to_bytes())?;
+            writeln!(text, "{}", record.to_text())?;
+        }
+        positions += records.len();
+        println!(
+            "info string game {} of {}, {} positions",
+            game + 1,
+            games,
+            positions
+        );
+    }
+    binary.flush()?;
+    text.flush()?;
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K1R1 b Qkq e3 7 42",
+            "8/8/8/8/8/8/3k4/K7 w - - 0 1",
+        ] {
+            let record = Record {
+                board: fen_to_board(fen),
+                score: -123,
+                result: 0.5,
+            };
+            let decoded = Record::from_bytes(&record.to_bytes());
+            assert_eq!(decoded, record);
+            assert_eq!(board_to_fen(&decoded.board), fen);
+        }
+    }
+
+    #[test]
+    fn plays_a_game() {
+        let options = SelfPlayOptions {
+            nodes: 200,
+            random_plies: 4,
+            ..SelfPlayOptions::default()
+        };
+        let records = loop {
+            if let Some(records) = play_game(&options) {
+                break records;
+            }
+        };
+        assert!(!records.is_empty());
+        let result = records[0].result;
+        assert!([0.0, 0.5, 1.0].contains(&result));
+        assert!(records.iter().all(|record| record.result == result));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn stops_when_writing_fails() {
+        // far more games than the test has time for, so it only ends if the workers stop
+        let options = SelfPlayOptions {
+            games: 100000,
+            threads: 2,
+            nodes: 50,
+            ..SelfPlayOptions::default()
+        };
+        let text = std::env::temp_dir().join(format!("ike-selfplay-{}.txt", std::process::id()));
+        let result = generate(&options, "/dev/full", text.to_str().unwrap());
+        let _ = std::fs::remove_file(&text);
+        assert!(result.is_err());
+    }
+}
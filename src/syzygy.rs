@@ -0,0 +1,1401 @@
+use crate::eval::piece_boards;
+use crate::movegen::{in_check, legal_moves, make_move};
+use crate::utils::*;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Syzygy endgame tablebases. A .rtbw file holds win, draw or loss for every position with its
+// material, and a .rtbz file holds the distance in plies to the next capture or pawn move (DTZ),
+// which is what it takes to win without running into the 50 move rule. The file layout and the
+// way positions are numbered follow the reference probing code.
+
+// Results from the side to move's point of view. Cursed wins and blessed losses are wins and
+// losses that the 50 move rule turns into draws.
+pub const LOSS: i32 = -2;
+pub const BLESSED_LOSS: i32 = -1;
+pub const DRAW: i32 = 0;
+pub const CURSED_WIN: i32 = 1;
+pub const WIN: i32 = 2;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const MAX_PIECES: usize = 7;
+
+// Per table flags
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+lazy_static! {
+    static ref ENCODING: Encoding = Encoding::new();
+}
+
+// Lookup tables for turning a position into its index in a table
+struct Encoding {
+    // a2 to h7 numbered from the edges in, so the leading pawn is the one with the highest number
+    map_pawns: [usize; 64],
+    // squares below the a1-h8 diagonal numbered 0 to 27
+    map_b1h1h7: [usize; 64],
+    // the a1-d1-d4 triangle numbered 0 to 9, diagonal last
+    map_a1d1d4: [usize; 64],
+    // the 462 legal placements of two kings with the first in the a1-d1-d4 triangle
+    map_kk: [[usize; 64]; 10],
+    // binomial[k][n] ways to choose k squares out of n
+    binomial: [[u64; 65]; 8],
+    lead_pawn_idx: [[u64; 64]; 8],
+    lead_pawns_size: [[u64; 4]; 8],
+}
+
+impl Encoding {
+    fn new() -> Encoding {
+        let mut encoding = Encoding {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 65]; 8],
+            lead_pawn_idx: [[0; 64]; 8],
+            lead_pawns_size: [[0; 4]; 8],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                encoding.map_b1h1h7[square as usize] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for square in 0..28 {
+            if off_diagonal(square) < 0 && square % 8 <= 3 {
+                encoding.map_a1d1d4[square as usize] = code;
+                code += 1;
+            } else if off_diagonal(square) == 0 && square % 8 <= 3 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            encoding.map_a1d1d4[square as usize] = code;
+            code += 1;
+        }
+
+        // with the first king on the diagonal the second one stays on or below it, and pairs
+        // that are both on the diagonal come last
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for first in 0..28u8 {
+                if encoding.map_a1d1d4[first as usize] != idx || (idx == 0 && first != 1) {
+                    continue;
+                }
+                for second in 0..64u8 {
+                    if (KING_MOVE_MASKS[first as usize] | 1 << first) & (1 << second) != 0 {
+                        continue;
+                    }
+                    if off_diagonal(first) == 0 && off_diagonal(second) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                        both_on_diagonal.push((idx, second));
+                    } else {
+                        encoding.map_kk[idx][second as usize] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            encoding.map_kk[idx][second as usize] = code;
+            code += 1;
+        }
+
+        encoding.binomial[0][0] = 1;
+        for n in 1..65 {
+            for k in 0..8.min(n + 1) {
+                let with = if k > 0 {
+                    encoding.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n {
+                    encoding.binomial[k][n - 1]
+                } else {
+                    0
+                };
+                encoding.binomial[k][n] = with + without;
+            }
+        }
+
+        // the leading pawn on a2 leaves 47 squares for the rest, and each rank further up takes
+        // away two more
+        let mut available = 47i32;
+        for lead_count in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_count == 1 {
+                        encoding.map_pawns[square] = available as usize;
+                        encoding.map_pawns[square ^ 7] = (available - 1) as usize;
+                        available -= 2;
+                    }
+                    encoding.lead_pawn_idx[lead_count][square] = idx;
+                    idx += encoding.binomial[lead_count - 1][encoding.map_pawns[square]];
+                }
+                encoding.lead_pawns_size[lead_count][file] = idx;
+            }
+        }
+
+        encoding
+    }
+}
+
+// Ranks above minus files across, so negative below the a1-h8 diagonal
+fn off_diagonal(square: u8) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+// What a table's name says about its material. White has the pieces before the v.
+#[derive(Clone)]
+struct Material {
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    symmetric: bool,
+    // pawns of the side whose pawns lead the encoding, then of the other side
+    pawn_count: [usize; 2],
+}
+
+impl Material {
+    fn parse(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let valid =
+            |side: &str| side.starts_with('K') && side[1..].chars().all(|c| "QRBNP".contains(c));
+        if !valid(white) || !valid(black) {
+            return None;
+        }
+
+        let count = |side: &str, piece: char| side.chars().filter(|&c| c == piece).count();
+        let has_unique_pieces = "QRBNP"
+            .chars()
+            .any(|piece| count(white, piece) == 1 || count(black, piece) == 1);
+        // the side with fewer pawns leads, as long as it has any
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let pawn_count = if white_leads {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+
+        Some(Material {
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            symmetric: white == black,
+            pawn_count,
+        })
+    }
+}
+
+// Decoding information for one side to move and leading pawn file of a table. Positions are
+// stored as values compressed with a canonical Huffman code in blocks of the same size. Symbols
+// stand for one value or for a pair of other symbols, so one symbol can expand to many values.
+// Offsets are into the table's bytes.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    // every span values there is an entry in the sparse index
+    span: u64,
+    num_blocks: usize,
+    max_sym_len: u8,
+    // with SINGLE_VALUE set, the value every position has
+    min_sym_len: u8,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    // base64[l] is the lowest symbol of length l + min_sym_len, padded to 64 bits
+    base64: Vec<u64>,
+    // how many values each symbol stands for, minus one
+    symlen: Vec<u8>,
+    // the order pieces are encoded in, which sets the groups
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    // where each result's DTZ value map starts
+    map_idx: [u16; 4],
+}
+
+struct Table {
+    bytes: Vec<u8>,
+    material: Material,
+    kind: Kind,
+    // [side to move][file of the leading pawn]
+    items: Vec<PairsData>,
+    // start of the DTZ value maps
+    map: usize,
+}
+
+fn byte(bytes: &[u8], at: usize) -> u8 {
+    *bytes.get(at).unwrap_or(&0)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([byte(bytes, at), byte(bytes, at + 1)])
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([0, 1, 2, 3].map(|i| byte(bytes, at + i)))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([0, 1, 2, 3].map(|i| byte(bytes, at + i)))
+}
+
+fn u64_be(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes([0, 1, 2, 3, 4, 5, 6, 7].map(|i| byte(bytes, at + i)))
+}
+
+// The two symbols a symbol expands to. A leaf has 0xFFF on the right and its value on the left.
+fn pair(bytes: &[u8], btree: usize, sym: usize) -> (usize, usize) {
+    let at = btree + 3 * sym;
+    let (a, b, c) = (
+        byte(bytes, at) as usize,
+        byte(bytes, at + 1) as usize,
+        byte(bytes, at + 2) as usize,
+    );
+    (((b & 0xF) << 8) | a, (c << 4) | (b >> 4))
+}
+
+impl Table {
+    fn new(bytes: Vec<u8>, material: Material, kind: Kind) -> Option<Table> {
+        let magic = if kind == Kind::Wdl {
+            WDL_MAGIC
+        } else {
+            DTZ_MAGIC
+        };
+        if bytes.len() < 5 || bytes[..4] != magic {
+            return None;
+        }
+
+        let mut items = vec![PairsData::default(); 8];
+        let sides = if kind == Kind::Wdl && !material.symmetric {
+            2
+        } else {
+            1
+        };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+
+        // after the magic and a byte of flags: the group order and the pieces for each file
+        let mut at = 5;
+        for file in 0..files {
+            let second = |shift: u32| {
+                if both_pawns {
+                    (byte(&bytes, at + 1) >> shift) & 0xF
+                } else {
+                    0xF
+                }
+            };
+            let order = [
+                [byte(&bytes, at) & 0xF, second(0)],
+                [byte(&bytes, at) >> 4, second(4)],
+            ];
+            at += 1 + both_pawns as usize;
+            for k in 0..material.piece_count {
+                items[file].pieces[k] = byte(&bytes, at) & 0xF;
+                items[4 + file].pieces[k] = byte(&bytes, at) >> 4;
+                at += 1;
+            }
+            for side in 0..sides {
+                let order = [order[side][0] as usize, order[side][1] as usize];
+                set_groups(&material, &mut items[side * 4 + file], order, file);
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                at = set_sizes(&bytes, &mut items[side * 4 + file], at);
+            }
+        }
+
+        let map = at;
+        if kind == Kind::Dtz {
+            at = set_dtz_map(&bytes, &mut items[..files], at, map);
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut items[side * 4 + file];
+                d.sparse_index = at;
+                at += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut items[side * 4 + file];
+                d.block_length = at;
+                at += d.block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut items[side * 4 + file];
+                // blocks start on 64 byte boundaries
+                at = (at + 0x3F) & !0x3F;
+                d.data = at;
+                at += d.num_blocks * d.block_size;
+            }
+        }
+        if at > bytes.len() {
+            return None;
+        }
+
+        Some(Table {
+            bytes,
+            material,
+            kind,
+            items,
+            map,
+        })
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let sides = if self.kind == Kind::Wdl { 2 } else { 1 };
+        let file = if self.material.has_pawns { file } else { 0 };
+        &self.items[(stm % sides) * 4 + file]
+    }
+
+    // The stored value for a position, and whether the table only has the other side to move.
+    // black_stronger says the position's colours are the other way round from the table's name.
+    fn probe(&self, board: &Board, black_stronger: bool, wdl: i32) -> (i32, bool) {
+        let encoding = &*ENCODING;
+        let material = &self.material;
+
+        // tables are for the first side in the name as White, and symmetric ones only have
+        // White to move
+        let flip = black_stronger || (material.symmetric && !board.turn);
+        let (flip_colour, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let stm = (flip == board.turn) as usize;
+
+        let (white, black) = piece_boards(board);
+        let mut codes = [0u8; 64];
+        for piece in 0..6 {
+            for square in set_bit_positions(white[piece]) {
+                codes[square as usize] = piece as u8 + 1;
+            }
+            for square in set_bit_positions(black[piece]) {
+                codes[square as usize] = piece as u8 + 9;
+            }
+        }
+
+        let mut squares = [0u8; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut lead_count = 0;
+        let mut file = 0;
+
+        // pawn tables are split by the file of the leading pawn, the one nearest the edge and
+        // lowest down, mirrored onto a to d
+        if material.has_pawns {
+            let piece = self.get(0, 0).pieces[0] ^ flip_colour;
+            lead_pawns = if piece < 8 { white[0] } else { black[0] };
+            for square in set_bit_positions(lead_pawns) {
+                squares[size] = square ^ flip_squares;
+                size += 1;
+            }
+            lead_count = size;
+            let lead = (0..lead_count)
+                .max_by_key(|&i| encoding.map_pawns[squares[i] as usize])
+                .unwrap();
+            squares.swap(0, lead);
+            file = (squares[0] % 8) as usize;
+            if file > 3 {
+                file = 7 - file;
+            }
+        }
+
+        if self.kind == Kind::Dtz {
+            let flags = self.get(stm, file).flags;
+            if (flags & STM) as usize != stm && (!material.symmetric || material.has_pawns) {
+                return (0, true);
+            }
+        }
+
+        for square in set_bit_positions((board.white | board.black) & !lead_pawns) {
+            squares[size] = square ^ flip_squares;
+            pieces[size] = codes[square as usize] ^ flip_colour;
+            size += 1;
+        }
+
+        // put the pieces in the order the table encodes them in
+        let d = self.get(stm, file);
+        for i in lead_count..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        if squares[0] % 8 > 3 {
+            for square in squares[..size].iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx;
+        if material.has_pawns {
+            idx = encoding.lead_pawn_idx[lead_count][squares[0] as usize];
+            squares[1..lead_count].sort_by_key(|&square| encoding.map_pawns[square as usize]);
+            for (i, &square) in squares[..lead_count].iter().enumerate().skip(1) {
+                idx += encoding.binomial[i][encoding.map_pawns[square as usize]];
+            }
+        } else {
+            // without pawns the leading piece can also be brought below the fifth rank and onto
+            // or below the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for square in squares[..size].iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for square in squares[i..size].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            idx = if material.has_unique_pieces {
+                leading_trio(&squares)
+            } else {
+                let first = encoding.map_a1d1d4[squares[0] as usize];
+                encoding.map_kk[first][squares[1] as usize] as u64
+            };
+        }
+
+        // the other groups, each as a combination of the squares the earlier groups left free
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i] as usize;
+                let below = squares[..start]
+                    .iter()
+                    .filter(|&&s| (s as usize) < square)
+                    .count();
+                let skipped = if remaining_pawns { 8 } else { 0 };
+                n += encoding.binomial[i + 1][square - below - skipped];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = decompress(&self.bytes, d, idx);
+        (self.map_score(file, value, wdl), false)
+    }
+
+    // Turns a stored value into a result, or into DTZ in plies
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        if self.kind == Kind::Wdl {
+            return value - 2;
+        }
+
+        let d = self.get(0, file);
+        let mut value = value;
+        // DTZ values are stored by how common they are, with a map back for each result
+        if d.flags & MAPPED != 0 {
+            let map = [1, 3, 0, 2, 0][(wdl + 2) as usize];
+            let i = d.map_idx[map] as usize + value as usize;
+            value = if d.flags & WIDE != 0 {
+                u16_le(&self.bytes, self.map + 2 * i) as i32
+            } else {
+                byte(&self.bytes, self.map + i) as i32
+            };
+        }
+
+        // some tables count in moves rather than plies
+        if (wdl == WIN && d.flags & WIN_PLIES == 0)
+            || (wdl == LOSS && d.flags & LOSS_PLIES == 0)
+            || wdl == CURSED_WIN
+            || wdl == BLESSED_LOSS
+        {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+// Index of the first three pieces when they are all different, with the first in the a1-d1-d4
+// triangle and the first one off the diagonal below it
+fn leading_trio(squares: &[u8]) -> u64 {
+    let encoding = &*ENCODING;
+    let [a, b, c] = [squares[0], squares[1], squares[2]].map(|s| s as u64);
+    let adjust1 = (b > a) as u64;
+    let adjust2 = (c > a) as u64 + (c > b) as u64;
+    let rank = |square: u64| square / 8;
+    let below = |square: u64| encoding.map_b1h1h7[square as usize] as u64;
+
+    if off_diagonal(squares[0]) != 0 {
+        (encoding.map_a1d1d4[a as usize] as u64 * 63 + (b - adjust1)) * 62 + c - adjust2
+    } else if off_diagonal(squares[1]) != 0 {
+        (6 * 63 + rank(a) * 28 + below(b)) * 62 + c - adjust2
+    } else if off_diagonal(squares[2]) != 0 {
+        6 * 63 * 62 + 4 * 28 * 62 + rank(a) * 7 * 28 + (rank(b) - adjust1) * 28 + below(c)
+    } else {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + rank(a) * 7 * 6
+            + (rank(b) - adjust1) * 6
+            + (rank(c) - adjust2)
+    }
+}
+
+// Splits the pieces into groups that are encoded together and works out what each group's index
+// is multiplied by. The groups come from runs of the same piece, except that the leading group
+// is the pawns of the leading side, three different pieces, or the two kings.
+fn set_groups(material: &Material, d: &mut PairsData, order: [usize; 2], file: usize) {
+    let encoding = &*ENCODING;
+    let mut n = 0;
+    let mut first_len: i32 = if material.has_pawns {
+        0
+    } else if material.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    d.group_len[0] = 1;
+    for i in 1..material.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    // order[0] and order[1] say where the leading group and the other side's pawns go among the
+    // groups, the rest follow in turn
+    let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+    let mut next = if both_pawns { 2 } else { 1 };
+    let mut free = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+    while next < n || k == order[0] || k == order[1] {
+        if k == order[0] {
+            d.group_idx[0] = idx;
+            idx *= if material.has_pawns {
+                encoding.lead_pawns_size[d.group_len[0]][file]
+            } else if material.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            d.group_idx[1] = idx;
+            idx *= encoding.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= encoding.binomial[d.group_len[next]][free];
+            free -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+// Reads the block sizes and the Huffman code, returning where the next record starts
+fn set_sizes(bytes: &[u8], d: &mut PairsData, mut at: usize) -> usize {
+    d.flags = byte(bytes, at);
+    at += 1;
+    if d.flags & SINGLE_VALUE != 0 {
+        d.min_sym_len = byte(bytes, at);
+        return at + 1;
+    }
+
+    let groups = d.group_len.iter().position(|&len| len == 0).unwrap_or(0);
+    let positions = d.group_idx[groups];
+    d.block_size = 1 << (byte(bytes, at) & 63);
+    d.span = 1 << (byte(bytes, at + 1) & 63);
+    d.sparse_index_size = positions.div_ceil(d.span) as usize;
+    let padding = byte(bytes, at + 2) as usize;
+    d.num_blocks = u32_le(bytes, at + 3) as usize;
+    d.block_length_size = d.num_blocks + padding;
+    d.max_sym_len = byte(bytes, at + 7);
+    d.min_sym_len = byte(bytes, at + 8);
+    at += 9;
+    d.lowest_sym = at;
+
+    // longer codes have lower values, so working up from the longest gives each length's
+    // lowest code, which is then padded out to 64 bits
+    let lengths = (d.max_sym_len as usize + 1).saturating_sub(d.min_sym_len as usize);
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths.saturating_sub(1)).rev() {
+        let lowest = u16_le(bytes, d.lowest_sym + 2 * i) as u64;
+        let next_lowest = u16_le(bytes, d.lowest_sym + 2 * i + 2) as u64;
+        d.base64[i] = d.base64[i + 1]
+            .wrapping_add(lowest)
+            .wrapping_sub(next_lowest)
+            / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        let shift = 64 - i as u32 - d.min_sym_len as u32;
+        *base = base.checked_shl(shift).unwrap_or(0);
+    }
+    at += lengths * 2;
+
+    let symbols = u16_le(bytes, at) as usize;
+    at += 2;
+    d.btree = at;
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(bytes, d.btree, &mut d.symlen, &mut visited, sym);
+        }
+    }
+    at + symbols * 3 + (symbols & 1)
+}
+
+// How many values a symbol expands to, minus one
+fn set_symlen(
+    bytes: &[u8],
+    btree: usize,
+    symlen: &mut [u8],
+    visited: &mut [bool],
+    sym: usize,
+) -> u8 {
+    visited[sym] = true;
+    let (left, right) = pair(bytes, btree, sym);
+    if right == 0xFFF || left >= symlen.len() || right >= symlen.len() {
+        return 0;
+    }
+    if !visited[left] {
+        symlen[left] = set_symlen(bytes, btree, symlen, visited, left);
+    }
+    if !visited[right] {
+        symlen[right] = set_symlen(bytes, btree, symlen, visited, right);
+    }
+    symlen[left].wrapping_add(symlen[right]).wrapping_add(1)
+}
+
+fn set_dtz_map(bytes: &[u8], items: &mut [PairsData], mut at: usize, map: usize) -> usize {
+    for d in items.iter_mut() {
+        if d.flags & MAPPED == 0 {
+            continue;
+        }
+        // each map starts with its length
+        if d.flags & WIDE != 0 {
+            at += at & 1;
+            for i in 0..4 {
+                d.map_idx[i] = ((at - map) / 2 + 1) as u16;
+                at += 2 * u16_le(bytes, at) as usize + 2;
+            }
+        } else {
+            for i in 0..4 {
+                d.map_idx[i] = (at - map + 1) as u16;
+                at += byte(bytes, at) as usize + 1;
+            }
+        }
+    }
+    at + (at & 1)
+}
+
+// The value stored at an index
+fn decompress(bytes: &[u8], d: &PairsData, idx: u64) -> i32 {
+    if d.flags & SINGLE_VALUE != 0 {
+        return d.min_sym_len as i32;
+    }
+
+    // the sparse index points near the value, then walk the block lengths the rest of the way
+    let entry = d.sparse_index + 6 * (idx / d.span) as usize;
+    let mut block = u32_le(bytes, entry) as usize;
+    let mut offset = u16_le(bytes, entry + 4) as i64;
+    offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+    let block_length = |block: usize| u16_le(bytes, d.block_length + 2 * block) as i64;
+    while offset < 0 {
+        block -= 1;
+        offset += block_length(block) + 1;
+    }
+    while offset > block_length(block) {
+        offset -= block_length(block) + 1;
+        block += 1;
+    }
+
+    // read symbols from the start of the block until reaching the one covering the offset
+    let min_len = d.min_sym_len as usize;
+    let mut at = d.data + block * d.block_size;
+    let mut buffer = u64_be(bytes, at);
+    let mut buffer_size = 64;
+    at += 8;
+    let mut sym;
+    loop {
+        let mut len = 0;
+        while len + 1 < d.base64.len() && buffer < d.base64[len] {
+            len += 1;
+        }
+        sym = (buffer.wrapping_sub(d.base64[len]) >> (64 - len - min_len)) as usize;
+        sym += u16_le(bytes, d.lowest_sym + 2 * len) as usize;
+        let values = *d.symlen.get(sym).unwrap_or(&0) as i64 + 1;
+        if offset < values {
+            break;
+        }
+        offset -= values;
+        len += min_len;
+        buffer <<= len;
+        buffer_size -= len;
+        if buffer_size <= 32 {
+            buffer_size += 32;
+            buffer |= (u32_be(bytes, at) as u64) << (64 - buffer_size);
+            at += 4;
+        }
+    }
+
+    // then expand it down to the single value
+    while *d.symlen.get(sym).unwrap_or(&0) != 0 {
+        let (left, right) = pair(bytes, d.btree, sym);
+        let left_values = d.symlen[left] as i64 + 1;
+        if offset < left_values {
+            sym = left;
+        } else {
+            offset -= left_values;
+            sym = right;
+        }
+    }
+    pair(bytes, d.btree, sym).0 as i32
+}
+
+// The DTZ of the move before a capture or pawn move with the given result
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        WIN => 1,
+        CURSED_WIN => 101,
+        BLESSED_LOSS => -101,
+        LOSS => -1,
+        _ => 0,
+    }
+}
+
+// A table's files, read the first time a position needs them
+struct TableFiles {
+    material: Material,
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl TableFiles {
+    fn table(&self, kind: Kind) -> Option<&Table> {
+        let (cell, path) = match kind {
+            Kind::Wdl => (&self.wdl, Some(&self.wdl_path)),
+            Kind::Dtz => (&self.dtz, self.dtz_path.as_ref()),
+        };
+        cell.get_or_init(|| {
+            let bytes = fs::read(path?).ok()?;
+            Table::new(bytes, self.material.clone(), kind)
+        })
+        .as_ref()
+    }
+}
+
+pub struct Tablebases {
+    // by name, like KRPvKR
+    tables: HashMap<String, TableFiles>,
+    pub max_pieces: u32,
+}
+
+impl fmt::Debug for Tablebases {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Tablebases {{ tables: {}, max_pieces: {} }}",
+            self.tables.len(),
+            self.max_pieces
+        )
+    }
+}
+
+impl Tablebases {
+    // Finds the .rtbw files in a list of directories separated the way PATH is, with the .rtbz
+    // files next to them or in any of the other directories
+    pub fn open(paths: &str) -> Tablebases {
+        let dirs: Vec<PathBuf> = env::split_paths(paths).collect();
+        let mut tables = HashMap::new();
+        let mut max_pieces = 0;
+        for dir in dirs.iter() {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let Some(material) = Material::parse(name) else {
+                    continue;
+                };
+                let dtz_path = dirs
+                    .iter()
+                    .map(|dir| dir.join(format!("{name}.rtbz")))
+                    .find(|path| path.exists());
+                max_pieces = max_pieces.max(material.piece_count as u32);
+                tables.entry(name.to_string()).or_insert(TableFiles {
+                    material,
+                    wdl_path: path.clone(),
+                    dtz_path,
+                    wdl: OnceLock::new(),
+                    dtz: OnceLock::new(),
+                });
+            }
+        }
+        Tablebases { tables, max_pieces }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    // Few enough pieces for the tables, and no castling, which they leave out
+    pub fn covers(&self, board: &Board) -> bool {
+        count_ones(board.white | board.black) as u32 <= self.max_pieces
+            && !(board.white_kingside_castle
+                || board.white_queenside_castle
+                || board.black_kingside_castle
+                || board.black_queenside_castle)
+    }
+
+    // The table for the position's material, and whether it has the colours the other way round
+    fn find(&self, board: &Board) -> Option<(&TableFiles, bool)> {
+        let (white, black) = piece_boards(board);
+        let name = |pieces: [u64; 6]| {
+            let mut name = String::from("K");
+            for (piece, letter) in [(4, 'Q'), (3, 'R'), (2, 'B'), (1, 'N'), (0, 'P')] {
+                for _ in 0..count_ones(pieces[piece]) {
+                    name.push(letter);
+                }
+            }
+            name
+        };
+        let (white, black) = (name(white), name(black));
+        if let Some(files) = self.tables.get(&format!("{white}v{black}")) {
+            return Some((files, false));
+        }
+        self.tables
+            .get(&format!("{black}v{white}"))
+            .map(|files| (files, true))
+    }
+
+    fn probe_table(&self, board: &Board, kind: Kind, wdl: i32) -> Option<(i32, bool)> {
+        if count_ones(board.white | board.black) == 2 {
+            return Some((DRAW, false));
+        }
+        let (files, black_stronger) = self.find(board)?;
+        let table = files.table(kind)?;
+        Some(table.probe(board, black_stronger, wdl))
+    }
+
+    // The tables may store anything for positions where a capture wins, and a loss where a
+    // capture draws, since those don't change the result. So the captures are searched too, and
+    // with pawn moves as well when DTZ is wanted, as DTZ isn't stored for positions whose best
+    // move is a capture or pawn move. Returns the result and whether such a move is best.
+    fn search(&self, board: &Board, pawn_moves: bool) -> Option<(i32, bool)> {
+        let moves = legal_moves(board);
+        let mut best = LOSS;
+        let mut searched = 0;
+        for ply in moves.iter() {
+            let pawn = ply.piece == WHITE_PAWN || ply.piece == BLACK_PAWN;
+            if ply.kind & CAPTURE == 0 && !(pawn_moves && pawn) {
+                continue;
+            }
+            searched += 1;
+            let value = -self.search(&make_move(board, ply), false)?.0;
+            if value > best {
+                best = value;
+                if value >= WIN {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // with every move searched the table isn't needed, and it might not even be right, as
+        // it doesn't know about en passant
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            self.probe_table(board, Kind::Wdl, DRAW)?.0
+        };
+        if best >= value {
+            return Some((best, best > DRAW || all_searched));
+        }
+        Some((value, false))
+    }
+
+    // Win, draw or loss for the side to move, or None without the tables for it
+    pub fn probe_wdl(&self, board: &Board) -> Option<i32> {
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    // Plies to the next capture or pawn move with best play, positive when the side to move
+    // wins and 0 in a draw. Off by one in some cases, like the reference code.
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        let (wdl, zeroing) = self.search(board, true)?;
+        if wdl == DRAW {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let (dtz, other_side) = self.probe_table(board, Kind::Dtz, wdl)?;
+        if !other_side {
+            let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum());
+        }
+
+        // the table only has the other side to move, so look one move ahead
+        let mut min_dtz = 0xFFFF;
+        for ply in legal_moves(board) {
+            let pawn = ply.piece == WHITE_PAWN || ply.piece == BLACK_PAWN;
+            let zeroing = ply.kind & CAPTURE != 0 || pawn;
+            let after = make_move(board, &ply);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.probe_wdl(&after)?)
+            } else {
+                -self.probe_dtz(&after)?
+            };
+            if dtz == 1 && in_check(&after, after.turn) && legal_moves(&after).is_empty() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    // The moves that keep the best result the tables give at the root. Among winning moves only
+    // those that reach the next capture or pawn move soonest are kept, so the win is converted
+    // before the 50 move rule, and among losing moves those that put it off the longest. Wins
+    // and losses that the 50 move rule would stop count as draws. None if any move can't be
+    // probed.
+    pub fn root_moves(&self, board: &Board, moves: &[Move]) -> Option<Vec<Move>> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        let halfmove = board.halfmove as i32;
+        let mut ranked = Vec::new();
+        for ply in moves {
+            let after = make_move(board, ply);
+            let mut dtz = if after.halfmove == 0 {
+                dtz_before_zeroing(-self.probe_wdl(&after)?)
+            } else {
+                let dtz = -self.probe_dtz(&after)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && in_check(&after, after.turn) && legal_moves(&after).is_empty() {
+                dtz = 1;
+            }
+
+            let rank = if dtz > 0 && dtz + halfmove <= 99 {
+                1000 - dtz
+            } else if dtz < 0 && -dtz + halfmove <= 99 {
+                -1000 - dtz
+            } else {
+                0
+            };
+            ranked.push((rank, ply.clone()));
+        }
+
+        let best = ranked.iter().map(|&(rank, _)| rank).max()?;
+        Some(
+            ranked
+                .into_iter()
+                .filter(|&(rank, _)| rank == best)
+                .map(|(_, ply)| ply)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_tables() {
+        let encoding = &*ENCODING;
+        // every placement of two kings gets its own number
+        let mut codes: Vec<usize> = Vec::new();
+        for idx in 0..10 {
+            for square in 0..64 {
+                codes.push(encoding.map_kk[idx][square]);
+            }
+        }
+        assert_eq!(codes.iter().max(), Some(&461));
+        assert_eq!(encoding.binomial[2][5], 10);
+        assert_eq!(encoding.map_pawns[8], 47);
+        assert_eq!(encoding.map_pawns[15], 46);
+        assert_eq!(encoding.lead_pawns_size[1][0], 6);
+    }
+
+    #[test]
+    fn names() {
+        let material = Material::parse("KRPvKR").unwrap();
+        assert_eq!(material.piece_count, 5);
+        assert!(material.has_pawns && material.has_unique_pieces && !material.symmetric);
+        assert_eq!(material.pawn_count, [1, 0]);
+        assert!(Material::parse("KvKQ").is_some());
+        assert!(Material::parse("KRRvKR").unwrap().has_unique_pieces);
+        assert!(!Material::parse("KNNvK").unwrap().has_unique_pieces);
+        assert!(Material::parse("KQK").is_none());
+        assert!(Material::parse("KXvK").is_none());
+    }
+
+    // A KQvK table where every position with White to move is won and every one with Black to
+    // move is lost, each stored as a single value
+    fn single_value_table() -> Vec<u8> {
+        let mut bytes = WDL_MAGIC.to_vec();
+        // split by side to move, no pawns
+        bytes.push(1);
+        // group order, then the pieces for each side to move: king, queen, king, then a byte to
+        // even up
+        bytes.push(0);
+        bytes.extend([0x66, 0x55, 0xEE, 0]);
+        // single values, stored as result + 2, and padding up to where blocks would start
+        bytes.extend([SINGLE_VALUE, 4, SINGLE_VALUE, 0]);
+        bytes.resize(64, 0);
+        bytes
+    }
+
+    // One side to move's part of a table compressed by compress
+    struct Compressed {
+        sizes: Vec<u8>,
+        sparse_index: Vec<u8>,
+        block_lengths: Vec<u8>,
+        blocks: Vec<u8>,
+    }
+
+    const BLOCK_SIZE: usize = 32;
+    const SPAN: usize = 1024;
+    // KQvK positions: the three pieces are all different and the first stays in the a1-d1-d4
+    // triangle
+    const KQVK_POSITIONS: usize = 31332;
+
+    // Compresses value(idx) for every position the way the tables do. Each value gets a leaf
+    // symbol and symbols for runs of 2, 4 .. 256 built from pairs of the next shorter run, and
+    // the rest of the 28 symbols are unused leaves. Symbols 0 to 15 have 6 bit codes, 16 to 23
+    // 5 bits and 24 to 27 3 bits, which makes a complete canonical code.
+    fn compress(flags: u8, value: impl Fn(usize) -> u8) -> Compressed {
+        let total = KQVK_POSITIONS.div_ceil(SPAN) * SPAN;
+        let values: Vec<u8> = (0..total).map(value).collect();
+        let mut distinct = values.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() <= 3);
+
+        // the symbol for a run of 2^k of the jth value
+        let number = |j: usize, k: usize| {
+            let p = 9 * j + 8 - k;
+            if p < 4 {
+                24 + p
+            } else if p < 12 {
+                16 + p - 4
+            } else {
+                p - 12
+            }
+        };
+        let code = |sym: usize| match sym {
+            24.. => (3, sym - 24 + 4),
+            16.. => (5, sym - 16 + 8),
+            _ => (6, sym),
+        };
+
+        let mut btree = [(0, 0xFFF); 28];
+        for (j, &v) in distinct.iter().enumerate() {
+            btree[number(j, 0)] = (v as usize, 0xFFF);
+            for k in 1..9 {
+                btree[number(j, k)] = (number(j, k - 1), number(j, k - 1));
+            }
+        }
+
+        // runs split into the longest symbols that fit, packed into blocks most significant bit
+        // first
+        let mut blocks: Vec<(Vec<bool>, usize)> = vec![(Vec::new(), 0)];
+        let mut start = 0;
+        while start < total {
+            let mut run = values[start..]
+                .iter()
+                .take_while(|&&v| v == values[start])
+                .count();
+            let j = distinct.iter().position(|&v| v == values[start]).unwrap();
+            start += run;
+            while run > 0 {
+                let k = run.min(256).ilog2() as usize;
+                let (len, bits) = code(number(j, k));
+                if blocks.last().unwrap().0.len() + len > 8 * BLOCK_SIZE {
+                    blocks.push((Vec::new(), 0));
+                }
+                let block = blocks.last_mut().unwrap();
+                block
+                    .0
+                    .extend((0..len).rev().map(|bit| bits >> bit & 1 != 0));
+                block.1 += 1 << k;
+                run -= 1 << k;
+            }
+        }
+
+        let mut sizes = vec![flags, BLOCK_SIZE.ilog2() as u8, SPAN.ilog2() as u8, 0];
+        sizes.extend((blocks.len() as u32).to_le_bytes());
+        // longest and shortest code, then the first symbol of each length from the shortest up
+        sizes.extend([6, 3]);
+        for lowest in [24u16, 24, 16, 0] {
+            sizes.extend(lowest.to_le_bytes());
+        }
+        sizes.extend(28u16.to_le_bytes());
+        for (left, right) in btree {
+            sizes.extend([
+                left as u8,
+                (left >> 8) as u8 | (right << 4) as u8,
+                (right >> 4) as u8,
+            ]);
+        }
+
+        // each entry points at the value in the middle of its span
+        let mut sparse_index = Vec::new();
+        let (mut block, mut first) = (0, 0);
+        for entry in 0..total / SPAN {
+            let middle = entry * SPAN + SPAN / 2;
+            while middle >= first + blocks[block].1 {
+                first += blocks[block].1;
+                block += 1;
+            }
+            sparse_index.extend((block as u32).to_le_bytes());
+            sparse_index.extend(((middle - first) as u16).to_le_bytes());
+        }
+
+        let mut block_lengths = Vec::new();
+        let mut bytes = Vec::new();
+        for (bits, count) in blocks.iter() {
+            block_lengths.extend(((count - 1) as u16).to_le_bytes());
+            let mut block = vec![0u8; BLOCK_SIZE];
+            for (i, &bit) in bits.iter().enumerate() {
+                block[i / 8] |= (bit as u8) << (7 - i % 8);
+            }
+            bytes.extend(block);
+        }
+
+        Compressed {
+            sizes,
+            sparse_index,
+            block_lengths,
+            blocks: bytes,
+        }
+    }
+
+    // The header both KQvK tables start with: magic, flags, the group order and the pieces in
+    // the order they are encoded, king, queen, king, for either side to move
+    fn kqvk_header(magic: [u8; 4]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend([0, 0, 0x66, 0x55, 0xEE, 0]);
+        bytes
+    }
+
+    fn pad_to_block(bytes: &mut Vec<u8>) {
+        bytes.resize(bytes.len().next_multiple_of(64), 0);
+    }
+
+    // Stored values, as result + 2 for WDL, in runs of different lengths so short and long run
+    // symbols both come up
+    fn white_to_move(idx: usize) -> u8 {
+        if idx % 5 == 4 || idx / 256 % 4 == 3 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn black_to_move(idx: usize) -> u8 {
+        if idx.is_multiple_of(3) {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Indexes into the DTZ value maps
+    fn dtz_value(idx: usize) -> u8 {
+        (idx / 10 % 3) as u8
+    }
+
+    // Moves to zeroing for each DTZ value of a win
+    const WIN_MAP: [u8; 3] = [5, 1, 20];
+
+    fn compressed_wdl() -> Vec<u8> {
+        let white = compress(0, white_to_move);
+        let black = compress(0, black_to_move);
+        let mut bytes = kqvk_header(WDL_MAGIC);
+        for part in [
+            &white.sizes,
+            &black.sizes,
+            &white.sparse_index,
+            &black.sparse_index,
+            &white.block_lengths,
+            &black.block_lengths,
+        ] {
+            bytes.extend(part);
+        }
+        for blocks in [&white.blocks, &black.blocks] {
+            pad_to_block(&mut bytes);
+            bytes.extend(blocks);
+        }
+        bytes
+    }
+
+    // White to move only, with values mapped and counted in moves
+    fn compressed_dtz() -> Vec<u8> {
+        let dtz = compress(MAPPED, dtz_value);
+        let mut bytes = kqvk_header(DTZ_MAGIC);
+        bytes.extend(&dtz.sizes);
+        // maps for wins, losses, cursed wins and blessed losses, each after its length
+        bytes.push(WIN_MAP.len() as u8);
+        bytes.extend(WIN_MAP);
+        bytes.extend([1, 1, 0, 0]);
+        bytes.resize(bytes.len() + bytes.len() % 2, 0);
+        bytes.extend(&dtz.sparse_index);
+        bytes.extend(&dtz.block_lengths);
+        pad_to_block(&mut bytes);
+        bytes.extend(&dtz.blocks);
+        bytes
+    }
+
+    #[test]
+    fn compressed_values() {
+        let material = Material::parse("KQvK").unwrap();
+        let wdl = Table::new(compressed_wdl(), material.clone(), Kind::Wdl).unwrap();
+        let dtz = Table::new(compressed_dtz(), material, Kind::Dtz).unwrap();
+        let white = wdl.get(0, 0);
+        assert!(white.num_blocks > 1);
+        assert_eq!(white.symlen[24], 255);
+        for idx in 0..KQVK_POSITIONS {
+            let expected = [white_to_move(idx), black_to_move(idx), dtz_value(idx)];
+            let decoded = [
+                decompress(&wdl.bytes, wdl.get(0, 0), idx as u64),
+                decompress(&wdl.bytes, wdl.get(1, 0), idx as u64),
+                decompress(&dtz.bytes, dtz.get(0, 0), idx as u64),
+            ];
+            assert_eq!(decoded, expected.map(|v| v as i32), "index {idx}");
+        }
+    }
+
+    #[test]
+    fn compressed_probe() {
+        let dir = env::temp_dir().join(format!("ike-syzygy-compressed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("KQvK.rtbw"), compressed_wdl()).unwrap();
+        fs::write(dir.join("KQvK.rtbz"), compressed_dtz()).unwrap();
+        let tablebases = Tablebases::open(dir.to_str().unwrap());
+        let wdl = |fen: &str| tablebases.probe_wdl(&fen_to_board(fen));
+        let dtz = |fen: &str| tablebases.probe_dtz(&fen_to_board(fen));
+
+        // The king on b1 is first in the triangle, so with the queen on d3 and the other king on
+        // h8 the index is (19 - 1) * 62 + 63 - 2
+        let a = 18 * 62 + 61;
+        assert_eq!(white_to_move(a), 4);
+        assert_eq!(wdl("7k/8/8/8/8/3Q4/8/1K6 w - - 0 1"), Some(WIN));
+        assert_eq!(black_to_move(a), 0);
+        assert_eq!(wdl("7k/8/8/8/8/3Q4/8/1K6 b - - 0 1"), Some(LOSS));
+        assert_eq!(dtz_value(a), 0);
+        assert_eq!(dtz("7k/8/8/8/8/3Q4/8/1K6 w - - 0 1"), Some(2 * 5 + 1));
+
+        // queen on a8 and the other king on h2
+        let b = 55 * 62 + 14;
+        assert_eq!(white_to_move(b), 2);
+        assert_eq!(wdl("Q7/8/8/8/8/8/7k/1K6 w - - 0 1"), Some(DRAW));
+        assert_eq!(dtz("Q7/8/8/8/8/8/7k/1K6 w - - 0 1"), Some(0));
+
+        // the king on c1 is second in the triangle, the queen on e2 and the other king on g7
+        let c = (63 + 11) * 62 + 52;
+        assert_eq!(white_to_move(c), 4);
+        assert_eq!(dtz_value(c), 2);
+        assert_eq!(wdl("8/6k1/8/8/8/8/4Q3/2K5 w - - 0 1"), Some(WIN));
+        assert_eq!(dtz("8/6k1/8/8/8/8/4Q3/2K5 w - - 0 1"), Some(2 * 20 + 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_value_probe() {
+        let dir = env::temp_dir().join(format!("ike-syzygy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("KQvK.rtbw"), single_value_table()).unwrap();
+        let tablebases = Tablebases::open(dir.to_str().unwrap());
+        assert_eq!(tablebases.len(), 1);
+        assert_eq!(tablebases.max_pieces, 3);
+
+        let probe = |fen: &str| tablebases.probe_wdl(&fen_to_board(fen));
+        assert_eq!(probe("8/8/8/3k4/8/8/8/Q3K3 w - - 0 1"), Some(WIN));
+        assert_eq!(probe("8/8/8/3k4/8/8/8/Q3K3 b - - 0 1"), Some(LOSS));
+        // the colours the other way round
+        assert_eq!(probe("q3k3/8/8/8/3K4/8/8/8 b - - 0 1"), Some(WIN));
+        assert_eq!(probe("q3k3/8/8/8/3K4/8/8/8 w - - 0 1"), Some(LOSS));
+        // the king can take the queen, which draws whatever the table says
+        assert_eq!(probe("8/8/8/8/8/8/3kQ3/K7 b - - 0 1"), Some(DRAW));
+        // no table for this material
+        assert_eq!(probe("8/8/8/3k4/8/8/8/R3K3 w - - 0 1"), None);
+        assert!(!tablebases.covers(&starting_position()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
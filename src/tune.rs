@@ -0,0 +1,309 @@
+use crate::eval::{eval_features, game_phase, MAX_PHASE};
+use crate::params::*;
+use crate::utils::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+// Texel tuning. A position's result is predicted as a sigmoid of its static evaluation, and the
+// weights are moved down the gradient of the mean squared error between predictions and results.
+// Apart from the tapering the eval is linear in the weights, so each position is boiled down once
+// to how many times it uses each weight and every step after that is cheap.
+
+// Constants for Adam, which scales each weight's step by how noisy its gradient has been
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+
+// One labelled position: each weight it uses with its count, White minus Black
+pub struct Sample {
+    features: Vec<(usize, i32)>,
+    // share of the middlegame score, phase / MAX_PHASE
+    phase: f64,
+    // 1 for a White win, 0.5 for a draw, 0 for a Black win
+    result: f64,
+}
+
+impl Sample {
+    pub fn new(board: &Board, result: f64) -> Sample {
+        let mut features: Vec<(usize, i32)> = Vec::new();
+        eval_features(board, &mut features);
+        features.sort_by_key(|&(index, _)| index);
+
+        // fold repeats together so each weight is looked at once per step
+        let mut merged: Vec<(usize, i32)> = Vec::new();
+        for (index, count) in features {
+            match merged.last_mut() {
+                Some(last) if last.0 == index => last.1 += count,
+                _ => merged.push((index, count)),
+            }
+        }
+        merged.retain(|&(_, count)| count != 0);
+
+        Sample {
+            features: merged,
+            phase: game_phase(board) as f64 / MAX_PHASE as f64,
+            result,
+        }
+    }
+
+    // Tapered centipawn score from White's point of view
+    fn evaluate(&self, weights: &[(f64, f64)]) -> f64 {
+        let (mut mg, mut eg) = (0.0, 0.0);
+        for &(index, count) in self.features.iter() {
+            mg += count as f64 * weights[index].0;
+            eg += count as f64 * weights[index].1;
+        }
+        mg * self.phase + eg * (1.0 - self.phase)
+    }
+}
+
+// Reads one position per line: a FEN followed by the result as 1-0, 0-1 or 1/2-1/2, or as
+// [1.0], [0.5] or [0.0]. Quotes and semicolons around the result, as in EPD files, are fine.
+// Lines that don't parse are skipped.
+pub fn load_samples(path: &str) -> io::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Some((board, result)) = parse_sample(&line?) {
+            samples.push(Sample::new(&board, result));
+        }
+    }
+    Ok(samples)
+}
+
+fn parse_sample(line: &str) -> Option<(Board, f64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 5 {
+        return None;
+    }
+
+    // EPD lines leave out the move counters
+    let counter = |field: &str| field.trim_end_matches(';').parse::<u32>().is_ok();
+    let counters = fields.len() >= 7 && counter(fields[4]) && counter(fields[5]);
+    let (fen, rest) = if counters {
+        (fields[..6].join(" ").replace(';', ""), &fields[6..])
+    } else {
+        (format!("{} 0 1", fields[..4].join(" ")), &fields[4..])
+    };
+
+    let result = rest.iter().find_map(|field| {
+        match field.trim_matches(|c| c == '"' || c == ';' || c == '[' || c == ']') {
+            "1-0" | "1.0" => Some(1.0),
+            "0-1" | "0.0" => Some(0.0),
+            "1/2-1/2" | "0.5" => Some(0.5),
+            _ => None,
+        }
+    })?;
+    Some((try_fen_to_board(&fen)?, result))
+}
+
+// Predicted result for a centipawn score
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+pub fn mean_error(samples: &[Sample], weights: &[(f64, f64)], k: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|sample| (sample.result - sigmoid(sample.evaluate(weights), k)).powi(2))
+        .sum();
+    total / samples.len() as f64
+}
+
+// The sigmoid scaling that fits the current weights best, so the tuner changes the weights rather
+// than stretching the whole scale
+pub fn fit_k(samples: &[Sample], weights: &[(f64, f64)]) -> f64 {
+    let (mut best, mut step) = (1.0, 0.5);
+    for _ in 0..4 {
+        let candidates = (-10..=10).map(|i| best + i as f64 * step / 10.0);
+        best = candidates
+            .filter(|&k| k > 0.0)
+            .map(|k| (k, mean_error(samples, weights, k)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0;
+        step /= 10.0;
+    }
+    best
+}
+
+// Gradient of mean_error with respect to each weight
+fn gradient(samples: &[Sample], weights: &[(f64, f64)], k: f64) -> Vec<(f64, f64)> {
+    let mut gradient = vec![(0.0, 0.0); weights.len()];
+    let scale = 2.0 * k * 10f64.ln() / 400.0 / samples.len() as f64;
+    for sample in samples {
+        let predicted = sigmoid(sample.evaluate(weights), k);
+        let error = (predicted - sample.result) * predicted * (1.0 - predicted) * scale;
+        for &(index, count) in sample.features.iter() {
+            gradient[index].0 += error * count as f64 * sample.phase;
+            gradient[index].1 += error * count as f64 * (1.0 - sample.phase);
+        }
+    }
+    gradient
+}
+
+// Runs the given number of full passes over the samples starting from params, reporting the error
+// every so often, and returns the tuned weights rounded back to centipawns
+pub fn tune(samples: &[Sample], params: &EvalParams, epochs: u32, rate: f64) -> EvalParams {
+    let mut weights: Vec<(f64, f64)> = params
+        .weights
+        .iter()
+        .map(|&(mg, eg)| (mg as f64, eg as f64))
+        .collect();
+    let k = fit_k(samples, &weights);
+    println!(
+        "{} positions, k {k:.3}, error {:.6}",
+        samples.len(),
+        mean_error(samples, &weights, k)
+    );
+
+    let mut momentum = vec![(0.0, 0.0); weights.len()];
+    let mut velocity = vec![(0.0, 0.0); weights.len()];
+    for epoch in 1..=epochs {
+        let gradient = gradient(samples, &weights, k);
+        for i in 0..weights.len() {
+            let (m, v) = (&mut momentum[i], &mut velocity[i]);
+            adam_step(
+                &mut weights[i].0,
+                &mut m.0,
+                &mut v.0,
+                gradient[i].0,
+                rate,
+                epoch,
+            );
+            adam_step(
+                &mut weights[i].1,
+                &mut m.1,
+                &mut v.1,
+                gradient[i].1,
+                rate,
+                epoch,
+            );
+        }
+
+        if epoch % 50 == 0 || epoch == epochs {
+            println!(
+                "epoch {epoch} error {:.6}",
+                mean_error(samples, &weights, k)
+            );
+        }
+    }
+
+    EvalParams {
+        weights: weights
+            .iter()
+            .map(|&(mg, eg)| (mg.round() as i32, eg.round() as i32))
+            .collect(),
+    }
+}
+
+// Moves one weight given its gradient and running averages
+fn adam_step(weight: &mut f64, m: &mut f64, v: &mut f64, g: f64, rate: f64, epoch: u32) {
+    *m = BETA1 * *m + (1.0 - BETA1) * g;
+    *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+    // the averages start at zero, so early on they are scaled up to make up for it
+    let m_hat = *m / (1.0 - BETA1.powi(epoch as i32));
+    let v_hat = *v / (1.0 - BETA2.powi(epoch as i32));
+    *weight -= rate * m_hat / (v_hat.sqrt() + EPSILON);
+}
+
+// Writes the weights as a Rust module with one constant per group and a tuned_params function
+// that puts them back together, ready to drop into src
+pub fn write_rust(params: &EvalParams, path: &str) -> io::Result<()> {
+    let mut out = File::create(path)?;
+    writeln!(out, "// Evaluation weights written by the Texel tuner")?;
+    writeln!(out, "use crate::params::{{EvalParams, PARAM_COUNT}};")?;
+
+    for group in GROUPS.iter() {
+        writeln!(out)?;
+        writeln!(out, "#[rustfmt::skip]")?;
+        writeln!(
+            out,
+            "pub const {}: [(i32, i32); {}] = [",
+            group.name, group.len
+        )?;
+        for (row, label) in params.rows(group) {
+            match label {
+                Some(label) => writeln!(out, "    {row} // {label}")?,
+                None => writeln!(out, "    {row}")?,
+            }
+        }
+        writeln!(out, "];")?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "pub fn tuned_params() -> EvalParams {{")?;
+    writeln!(
+        out,
+        "    let mut weights = Vec::with_capacity(PARAM_COUNT);"
+    )?;
+    for group in GROUPS.iter() {
+        writeln!(out, "    weights.extend_from_slice(&{});", group.name)?;
+    }
+    writeln!(out, "    EvalParams {{ weights }}")?;
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval;
+
+    #[test]
+    fn parse_formats() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+        for (line, expected) in [
+            (format!("{start} 0 1 [1.0]"), 1.0),
+            (format!("{start} 0 1; 0-1"), 0.0),
+            (format!("{start} c9 \"1/2-1/2\";"), 0.5),
+        ] {
+            let (board, result) = parse_sample(&line).unwrap();
+            assert_eq!(result, expected);
+            assert_eq!(board.white, starting_position().white);
+        }
+        assert!(parse_sample(&format!("{start} 0 1")).is_none());
+        // a broken FEN is skipped like any other bad line
+        assert!(parse_sample("rnbqkbnr/pppppppp/8/8 w KQkq - 0 1 [1.0]").is_none());
+    }
+
+    #[test]
+    fn samples_match_eval() {
+        let params = EvalParams::default();
+        let weights: Vec<(f64, f64)> = params
+            .weights
+            .iter()
+            .map(|&(mg, eg)| (mg as f64, eg as f64))
+            .collect();
+        let board =
+            fen_to_board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let sample = Sample::new(&board, 1.0);
+        // the engine rounds when it tapers
+        assert!((sample.evaluate(&weights) / 100.0 - eval(&board, &params) as f64).abs() < 0.01);
+    }
+
+    #[test]
+    fn gradient_matches_slope() {
+        let samples: Vec<Sample> = [
+            ("4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1", 1.0),
+            ("4k3/pp6/8/8/3N4/8/8/4K3 b - - 0 1", 0.5),
+            ("r5k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", 0.0),
+        ]
+        .iter()
+        .map(|&(fen, result)| Sample::new(&fen_to_board(fen), result))
+        .collect();
+        let weights: Vec<(f64, f64)> = EvalParams::default()
+            .weights
+            .iter()
+            .map(|&(mg, eg)| (mg as f64, eg as f64))
+            .collect();
+
+        let analytic = gradient(&samples, &weights, 1.0);
+        for index in [PIECE_VALUE, PIECE_VALUE + 1, PIECE_VALUE + 3, DOUBLED] {
+            let mut higher = weights.clone();
+            let mut lower = weights.clone();
+            higher[index].1 += 0.5;
+            lower[index].1 -= 0.5;
+            let slope = mean_error(&samples, &higher, 1.0) - mean_error(&samples, &lower, 1.0);
+            assert!((slope - analytic[index].1).abs() < 1e-6);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::eval::material_and_pst;
 use crate::hash::zobrist_hash;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
@@ -60,6 +61,8 @@ fn blank_board() -> Board {
         ep_target: None,
         halfmove: 0,
         fullmove: 0,
+        psqt_mg: 0,
+        psqt_eg: 0,
     }
 }
 
@@ -87,6 +90,8 @@ pub fn starting_position() -> Board {
         ep_target: None,
         halfmove: 0,
         fullmove: 1,
+        psqt_mg: 0,
+        psqt_eg: 0,
     }
 }
 
@@ -123,6 +128,67 @@ pub fn index_to_square(index: u8) -> String {
     square
 }
 
+// Long algebraic notation used by UCI, e.g. e2e4 or e7e8q
+pub fn move_to_uci(ply: &Move) -> String {
+    let mut name = format!("{}{}", SQUARES[ply.from as usize], SQUARES[ply.to as usize]);
+    match ply.kind {
+        KNIGHT_PROMO | KNIGHT_PROMO_CAPTURE => name.push('n'),
+        BISHOP_PROMO | BISHOP_PROMO_CAPTURE => name.push('b'),
+        ROOK_PROMO | ROOK_PROMO_CAPTURE => name.push('r'),
+        QUEEN_PROMO | QUEEN_PROMO_CAPTURE => name.push('q'),
+        _ => {}
+    }
+    name
+}
+
+// Like fen_to_board, but None for a FEN it would panic on or one without exactly one king a side,
+// for reading positions from files that may have bad lines
+pub fn try_fen_to_board(fen: &str) -> Option<Board> {
+    let parts: Vec<&str> = fen.split(' ').collect();
+    if parts.len() < 6 {
+        return None;
+    }
+
+    let ranks: Vec<&str> = parts[0].split('/').collect();
+    if ranks.len() != 8 {
+        return None;
+    }
+    for rank in ranks {
+        let mut files = 0;
+        for square in rank.chars() {
+            files += match square {
+                '1'..='8' => square.to_digit(10).unwrap(),
+                'p' | 'n' | 'b' | 'r' | 'q' | 'k' | 'P' | 'N' | 'B' | 'R' | 'Q' | 'K' => 1,
+                _ => return None,
+            };
+        }
+        if files != 8 {
+            return None;
+        }
+    }
+    if parts[0].matches('K').count() != 1 || parts[0].matches('k').count() != 1 {
+        return None;
+    }
+
+    let target: Vec<char> = parts[3].chars().collect();
+    let valid_target = match target[..] {
+        ['-'] => true,
+        [file, rank] => ('a'..='h').contains(&file) && ('1'..='8').contains(&rank),
+        _ => false,
+    };
+    if !["w", "b"].contains(&parts[1])
+        || parts[2].is_empty()
+        || !parts[2].chars().all(|right| "-KQkq".contains(right))
+        || !valid_target
+        || parts[4].parse::<u16>().is_err()
+        || parts[5].parse::<u16>().is_err()
+    {
+        return None;
+    }
+
+    Some(fen_to_board(fen))
+}
+
 // expects valid fen
 pub fn fen_to_board(fen: &str) -> Board {
     let mut board = blank_board();
@@ -243,16 +309,109 @@ pub fn fen_to_board(fen: &str) -> Board {
 
     if target != "-" {
         let file = target.chars().next().unwrap() as u8 - 'a' as u8;
-        let rank = target.chars().next().unwrap().to_digit(10).unwrap();
-        board.ep_target = Some((8 * rank) as u8 + file);
+        let rank = target.chars().nth(1).unwrap().to_digit(10).unwrap();
+        board.ep_target = Some((8 * (rank - 1)) as u8 + file);
     }
 
-    board.halfmove = parts[4].chars().next().unwrap() as u16;
-    board.fullmove = parts[5].chars().next().unwrap() as u16;
+    board.halfmove = parts[4].parse().unwrap();
+    board.fullmove = parts[5].parse().unwrap();
+
+    (board.psqt_mg, board.psqt_eg) = material_and_pst(&board);
 
     board
 }
 
+// The inverse of fen_to_board
+pub fn board_to_fen(board: &Board) -> String {
+    let pieces = [
+        (board.white_pawn, 'P'),
+        (board.white_knight, 'N'),
+        (board.white_bishop, 'B'),
+        (board.white_rook, 'R'),
+        (board.white_queen, 'Q'),
+        (board.white_king, 'K'),
+        (board.black_pawn, 'p'),
+        (board.black_knight, 'n'),
+        (board.black_bishop, 'b'),
+        (board.black_rook, 'r'),
+        (board.black_queen, 'q'),
+        (board.black_king, 'k'),
+    ];
+
+    let mut fen = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            let square = 1u64 << (8 * rank + file);
+            match pieces.iter().find(|(bitboard, _)| bitboard & square != 0) {
+                Some(&(_, letter)) => {
+                    if empty > 0 {
+                        fen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen.push(letter);
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+        if rank > 0 {
+            fen.push('/');
+        }
+    }
+
+    fen.push_str(if board.turn { " w " } else { " b " });
+    let rights: String = [
+        (board.white_kingside_castle, 'K'),
+        (board.white_queenside_castle, 'Q'),
+        (board.black_kingside_castle, 'k'),
+        (board.black_queenside_castle, 'q'),
+    ]
+    .iter()
+    .filter(|(allowed, _)| *allowed)
+    .map(|&(_, letter)| letter)
+    .collect();
+    fen.push_str(if rights.is_empty() { "-" } else { &rights });
+    match board.ep_target {
+        Some(square) => fen.push_str(&format!(" {}", SQUARES[square as usize])),
+        None => fen.push_str(" -"),
+    }
+    fen.push_str(&format!(" {} {}", board.halfmove, board.fullmove));
+    fen
+}
+
+// The same position seen from the other side: ranks flipped, colours swapped and the other side
+// to move. A symmetric evaluation scores it as the negation of the original.
+pub fn mirror(board: &Board) -> Board {
+    let mut mirrored = Board {
+        white: board.black.swap_bytes(),
+        black: board.white.swap_bytes(),
+        white_pawn: board.black_pawn.swap_bytes(),
+        white_knight: board.black_knight.swap_bytes(),
+        white_bishop: board.black_bishop.swap_bytes(),
+        white_rook: board.black_rook.swap_bytes(),
+        white_queen: board.black_queen.swap_bytes(),
+        white_king: board.black_king.swap_bytes(),
+        black_pawn: board.white_pawn.swap_bytes(),
+        black_knight: board.white_knight.swap_bytes(),
+        black_bishop: board.white_bishop.swap_bytes(),
+        black_rook: board.white_rook.swap_bytes(),
+        black_queen: board.white_queen.swap_bytes(),
+        black_king: board.white_king.swap_bytes(),
+        turn: !board.turn,
+        white_kingside_castle: board.black_kingside_castle,
+        white_queenside_castle: board.black_queenside_castle,
+        black_kingside_castle: board.white_kingside_castle,
+        black_queenside_castle: board.white_queenside_castle,
+        ep_target: board.ep_target.map(|square| square ^ 56),
+        ..*board
+    };
+    (mirrored.psqt_mg, mirrored.psqt_eg) = material_and_pst(&mirrored);
+    mirrored
+}
+
 // The Board representation.
 #[derive(Clone, Debug)]
 pub struct Board {
@@ -279,6 +438,9 @@ pub struct Board {
     pub ep_target: Option<u8>,
     pub halfmove: u16,
     pub fullmove: u16,
+    // material plus piece-square scores, White minus Black, kept up to date by make_move
+    pub psqt_mg: i32,
+    pub psqt_eg: i32,
 }
 
 impl PartialEq for Board {
@@ -752,4 +914,47 @@ mod tests {
         let g7 = index_to_square(54);
         assert_eq!(g7, "g7");
     }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K1R1 b Qkq e3 0 1",
+            "8/2k5/3p4/p2P1p2/P4P2/4K3/8/8 w - - 12 40",
+        ] {
+            assert_eq!(board_to_fen(&fen_to_board(fen)), fen);
+        }
+    }
+
+    #[test]
+    fn bad_fens() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(try_fen_to_board(start), Some(fen_to_board(start)));
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -",
+            "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQxq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+        ] {
+            assert_eq!(try_fen_to_board(fen), None, "{fen}");
+        }
+    }
+
+    #[test]
+    fn mirror_board() {
+        let board =
+            fen_to_board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K1R1 b Qkq e3 0 1");
+        let mirrored = mirror(&board);
+        assert_eq!(
+            mirrored,
+            fen_to_board("r3k1r1/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R w KQq e6 0 1")
+        );
+        assert_eq!(mirrored.psqt_mg, -board.psqt_mg);
+        assert_eq!(mirror(&mirrored), board);
+    }
 }